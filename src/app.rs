@@ -1,250 +1,784 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
 use eframe::egui;
+use egui::{Color32, Rounding};
 use crate::pix_grid::*;
+use crate::grid_view::GridView;
+use crate::pipeline::Pipeline;
+use crate::graph::Graph;
+use crate::graph_editor::GraphEditor;
+use crate::image::ImageBuf;
+use crate::history::History;
+use crate::lut::{LutPreset, PointOp};
+use crate::histogram::HistogramCache;
+use crate::import::{self, ImageLoader, ImportSettings, Resampling};
+use crate::export::{self, ExportFormat, ExportSettings};
+use crate::morphology::{self, MorphOp};
+use crate::gradient::{self, DerivativePair, Gradient, GradientOutput};
+use crate::canny::{Canny, CannyCache, CannyImages, CannyStage};
+use crate::color::{Conversion, LumaWeights, CHANNEL_NAMES};
+use crate::fourier::{self, FilterBand, FilterShape, FourierFilter, SpectrumCache, SpectrumView};
+use crate::threshold::{self, OtsuCache, ThresholdMode, Thresholding, MAX_ADAPTIVE_RADIUS};
+use crate::kernel::{Convolution, Mask, MaskShape, Param, PresetParams, MAX_CONV_EXTENT, PRESETS};
 
-#[derive(PartialEq)]
-enum Tool {
+#[derive(PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum Tool {
     Pen,
     Conv,
     Cpy,
     Boolean,
+    Rank,
+    Morph,
+    Threshold,
+    Lut,
+    Gradient,
+    Canny,
+    Frequency,
+}
+
+/// parameters of the grayscale morphology tool
+#[derive(PartialEq, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+struct Morphology {
+    op: MorphOp,
+    /// structuring element
+    se: Mask,
+    /// last chosen shape for the structuring element
+    shape: MaskShape,
+    /// direction of `MaskShape::Line` in degrees
+    angle: f32,
+}
+
+impl Default for Morphology {
+    fn default() -> Self {
+        Self {
+            op: MorphOp::Erode,
+            se: Mask::from_shape(MaskShape::Square, 1, 0.0),
+            shape: MaskShape::Square,
+            angle: 0.0,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+enum RankOp {
+    Median,
+    Min,
+    Max,
+    Percentile,
+}
+
+/// parameters of the rank filter, which sorts the values in the neighbourhood and picks one
+#[derive(PartialEq, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+struct RankFilter {
+    mask: Mask,
+    op: RankOp,
+    /// for `RankOp::Percentile`, 0 is the minimum and 100 the maximum
+    percentile: f32,
+}
+
+impl Default for RankFilter {
+    fn default() -> Self {
+        Self {
+            mask: Mask { radius: 1, mask: vec![vec![true; 3]; 3] },
+            op: RankOp::Median,
+            percentile: 50.0,
+        }
+    }
+}
+
+impl RankFilter {
+    /// the percentile the operation picks, 0 is the minimum and 100 the maximum
+    fn percentile(&self) -> f32 {
+        match self.op {
+            RankOp::Median => 50.0,
+            RankOp::Min => 0.0,
+            RankOp::Max => 100.0,
+            RankOp::Percentile => self.percentile,
+        }
+    }
 }
 
 // the tools' parameters are stored in an own struct rather than inside the tool enum to make them
 // persistent over tool changes (the pen should keep its color after switching to another tool...)
+#[derive(PartialEq, Clone, serde::Deserialize, serde::Serialize)]
 pub struct ToolVars {
     pen_color: u8,
+    /// the pen color for rgb grids
+    #[serde(default = "default_pen_rgb")]
+    pen_rgb: [u8; 3],
     conv: Convolution,
     boolean_mask: [[bool; 3];3],
     boolean_dilation: bool,
+    /// values above this count as true for the boolean operation
+    #[serde(default = "default_boolean_threshold")]
+    boolean_threshold: u8,
+    #[serde(default)]
+    border: Border,
+    /// index of the last chosen convolution preset
+    #[serde(default)]
+    preset: usize,
+    #[serde(default)]
+    preset_params: PresetParams,
+    #[serde(default)]
+    rank: RankFilter,
+    #[serde(default)]
+    morph: Morphology,
+    #[serde(default)]
+    threshold: Thresholding,
+    #[serde(default)]
+    point_op: PointOp,
+    #[serde(default)]
+    gradient: Gradient,
+    #[serde(default)]
+    canny: Canny,
+    #[serde(default)]
+    fourier: FourierFilter,
+    /// used whenever an rgb image becomes gray
+    #[serde(default)]
+    luma: LumaWeights,
+    #[serde(default)]
+    conversion: Conversion,
+    /// write the kernel weights into the hovered neighbourhood
+    #[serde(default)]
+    show_weights: bool,
+    /// changes whenever the parameters change, so the pipeline can find out cheaply
+    #[serde(skip)]
+    revision: u64,
+}
+
+fn default_boolean_threshold() -> u8 { 127 }
+
+fn default_pen_rgb() -> [u8; 3] { [200, 40, 40] }
+
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(1);
+
+impl ToolVars {
+    pub fn revision(&self) -> u64 { self.revision }
+
+    /// has to be called after changing the parameters
+    fn touch(&mut self) {
+        self.revision = NEXT_REVISION.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// check loaded parameters, since the tools index the kernels and masks without checks.
+    /// neighbourhoods larger than the controls allow would also make every frame slow.
+    pub fn is_consistent(&self) -> bool {
+        let conv = &self.conv;
+        let conv_ok = conv.left <= 0 && conv.right >= 0 && conv.up <= 0 && conv.down >= 0
+            && [-conv.left, conv.right, -conv.up, conv.down].iter().all(|&e| e <= MAX_CONV_EXTENT)
+            && conv.mask.len() == (conv.down - conv.up + 1) as usize
+            && conv.mask.iter().all(|row| row.len() == (conv.right - conv.left + 1) as usize);
+        conv_ok && self.preset < PRESETS.len()
+            && self.rank.mask.is_consistent() && self.rank.mask.radius <= MAX_CONV_EXTENT
+            && self.morph.se.is_consistent() && self.morph.se.radius <= MAX_CONV_EXTENT
+            && (1..=MAX_ADAPTIVE_RADIUS).contains(&self.threshold.radius)
+            && (0..=MAX_CONV_EXTENT).contains(&self.canny.radius)
+            && self.point_op.is_consistent()
+    }
+
+    /// whether the painted mask of the frequency filter was made for a grid of this size.
+    /// on other sizes the filter applies its preset instead.
+    pub fn filter_fits(&self, width: u32, height: u32) -> bool {
+        self.fourier.fits(width, height)
+    }
+
+    /// give the frequency filter the size of the grid
+    fn ensure_filter_size(&mut self, width: u32, height: u32) {
+        if !self.fourier.fits(width, height) {
+            self.fourier.ensure_size(width, height);
+            self.touch();
+        }
+    }
+
+    /// the values the pen sets, one per channel
+    fn pen_values(&self, color: bool) -> Vec<f32> {
+        if color {
+            self.pen_rgb.iter().map(|&v| v as f32).collect()
+        } else {
+            vec![self.pen_color as f32]
+        }
+    }
+
+    fn pen_color32(&self, color: bool) -> Color32 {
+        if color {
+            Color32::from_rgb(self.pen_rgb[0], self.pen_rgb[1], self.pen_rgb[2])
+        } else {
+            Color32::from_gray(self.pen_color)
+        }
+    }
 }
 
 impl Tool {
-    fn interact(&self, ui: &mut egui::Ui, tool_vars: &ToolVars, src_grid: &mut PixGrid, dst_grid: &mut PixGrid) {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tool::Pen => "Pen",
+            Tool::Conv => "Convolution",
+            Tool::Cpy => "Copy",
+            Tool::Boolean => "Boolean operation",
+            Tool::Rank => "Rank filter",
+            Tool::Morph => "Morphology",
+            Tool::Threshold => "Threshold",
+            Tool::Lut => "Point operation",
+            Tool::Gradient => "Gradient",
+            Tool::Canny => "Canny",
+            Tool::Frequency => "Frequency filter",
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn interact(&self, ui: &mut egui::Ui, tool_vars: &ToolVars, canny_cache: &CannyCache, otsu: &[u8], src_grid: &mut PixGrid, src_view: &GridView, dst_grid: &mut PixGrid, dst_view: &GridView) {
+        let Some((ix, iy)) = src_view.hovered_idx() else {
+            return;
+        };
+        let pressed = src_view.pressed();
         match self {
             Tool::Pen => {
-                if let Some((ix, iy)) = src_grid.hovered_idx() {
-                    src_grid.draw_outline(ui, ix, iy, ix, iy);
-                    src_grid.draw_rect_at_idx(ui, ix, iy, tool_vars.pen_color);
-                    if src_grid.pressed() {
-                        src_grid.try_set(ix as i32, iy as i32, tool_vars.pen_color);
-                    }
+                src_view.draw_outline(ui, src_grid, ix, iy, ix, iy);
+                src_view.draw_rect_at_idx(ui, ix, iy, tool_vars.pen_color32(src_grid.is_color()));
+                if pressed {
+                    src_grid.try_set_pixel(ix as i32, iy as i32, &tool_vars.pen_values(src_grid.is_color()));
                 }
             },
             Tool::Conv => {
-                if let Some((ix, iy)) = src_grid.hovered_idx() {
-                    let conv = &tool_vars.conv;
-                    src_grid.draw_outline_clamped(
-                        ui,
-                        ix as i32 + conv.left,
-                        iy as i32 + conv.up,
-                        ix as i32 + conv.right,
-                        iy as i32 + conv.down);
-                    dst_grid.draw_outline_clamped(ui, ix as i32, iy as i32, ix as i32, iy as i32);
-
-                    let color = Tool::convolution(ix, iy, &conv, &src_grid);
-
-                    dst_grid.try_draw_rect_at_idx(ui, ix as i32, iy as i32, color);
-                    if src_grid.pressed() {
-                        dst_grid.try_set(ix as i32, iy as i32, color);
+                let conv = &tool_vars.conv;
+                src_view.draw_outline_clamped(
+                    ui,
+                    src_grid,
+                    ix as i32 + conv.left,
+                    iy as i32 + conv.up,
+                    ix as i32 + conv.right,
+                    iy as i32 + conv.down);
+                let taps = (conv.up..=conv.down)
+                    .flat_map(|y| (conv.left..=conv.right).map(move |x| (ix as i32 + x, iy as i32 + y)));
+                Tool::draw_border_taps(ui, &tool_vars.border, src_grid, src_view, taps);
+                if tool_vars.show_weights {
+                    for (y, row) in conv.mask.iter().enumerate() {
+                        for (x, weight) in row.iter().enumerate() {
+                            let (x, y) = (ix as i32 + conv.left + x as i32, iy as i32 + conv.up + y as i32);
+                            src_view.draw_annotation_at_idx(ui, x, y, &format_value(*weight, 2));
+                        }
                     }
                 }
+                dst_view.draw_outline_clamped(ui, dst_grid, ix as i32, iy as i32, ix as i32, iy as i32);
+
+                let values = self.values_at(tool_vars, src_grid, ix as i32, iy as i32);
+
+                Tool::show_result(ui, pressed, dst_grid, dst_view, ix as i32, iy as i32, values);
             },
-            Tool::Cpy => {
-                if let Some((ix, iy)) = src_grid.hovered_idx() {
-                    src_grid.draw_outline(ui, ix, iy, ix, iy);
-                    dst_grid.draw_outline_clamped(ui, ix as i32, iy as i32, ix as i32, iy as i32);
-                    let color = src_grid.get(ix, iy);
-                    dst_grid.try_draw_rect_at_idx(ui, ix as i32, iy as i32, color);
-                    if src_grid.pressed() {
-                        dst_grid.try_set(ix as i32, iy as i32, src_grid.get_clamped(ix as i32, iy as i32));
-                    }
-                }
+            Tool::Cpy | Tool::Lut => {
+                src_view.draw_outline(ui, src_grid, ix, iy, ix, iy);
+                dst_view.draw_outline_clamped(ui, dst_grid, ix as i32, iy as i32, ix as i32, iy as i32);
+                let values = self.values_at(tool_vars, src_grid, ix as i32, iy as i32);
+                Tool::show_result(ui, pressed, dst_grid, dst_view, ix as i32, iy as i32, values);
             },
             Tool::Boolean => {
-                if let Some((ix, iy)) = src_grid.hovered_idx() {
-                    let ix = ix as i32;
-                    let iy = iy as i32;
-                    for y_off in -1..=1 {
-                        for x_off in -1..=1 {
-                            if tool_vars.boolean_mask[(y_off + 1) as usize][(x_off + 1) as usize] {
-                                src_grid.draw_outline_clamped(ui, ix + x_off, iy + y_off, ix + x_off, iy + y_off);
-                            }
+                let ix = ix as i32;
+                let iy = iy as i32;
+                for y_off in -1..=1 {
+                    for x_off in -1..=1 {
+                        // taps outside of the image are drawn below, depending on the border mode
+                        let (x, y) = (ix + x_off, iy + y_off);
+                        if tool_vars.boolean_mask[(y_off + 1) as usize][(x_off + 1) as usize] && src_grid.in_bounds(x, y) {
+                            src_view.draw_outline(ui, src_grid, x as u32, y as u32, x as u32, y as u32);
                         }
                     }
-                    dst_grid.draw_outline_clamped(ui, ix, iy, ix, iy);
-                    let color = Tool::bool_op(ix, iy, &tool_vars, &src_grid);
-                    dst_grid.try_draw_rect_at_idx(ui, ix, iy, color);
-                    if src_grid.pressed() {
-                        dst_grid.try_set(ix, iy, color);
+                }
+                let taps = (-1..=1).flat_map(|y| (-1..=1).map(move |x| (x, y)))
+                    .filter(|&(x, y)| tool_vars.boolean_mask[(y + 1) as usize][(x + 1) as usize])
+                    .map(|(x, y)| (ix + x, iy + y));
+                Tool::draw_border_taps(ui, &tool_vars.border, src_grid, src_view, taps);
+                dst_view.draw_outline_clamped(ui, dst_grid, ix, iy, ix, iy);
+                let values = self.values_at(tool_vars, src_grid, ix, iy);
+                Tool::show_result(ui, pressed, dst_grid, dst_view, ix, iy, values);
+            },
+            Tool::Rank | Tool::Morph | Tool::Threshold => {
+                let (ix, iy) = (ix as i32, iy as i32);
+                let offsets = match self {
+                    Tool::Rank => tool_vars.rank.mask.offsets().collect(),
+                    // for the composite operations, this shows everything the result depends on
+                    Tool::Morph => tool_vars.morph.op.footprint(&tool_vars.morph.se),
+                    _ => tool_vars.threshold.window(),
+                };
+                let taps: Vec<_> = offsets.into_iter().map(|(x, y)| (ix + x, iy + y)).collect();
+                for &(x, y) in taps.iter().filter(|&&(x, y)| src_grid.in_bounds(x, y)) {
+                    src_view.draw_outline(ui, src_grid, x as u32, y as u32, x as u32, y as u32);
+                }
+                Tool::draw_border_taps(ui, &tool_vars.border, src_grid, src_view, taps.into_iter());
+                dst_view.draw_outline_clamped(ui, dst_grid, ix, iy, ix, iy);
+                let values = if *self == Tool::Threshold {
+                    (0..src_grid.channel_count()).map(|c| {
+                        let otsu_value = otsu.get(c).copied().unwrap_or_default();
+                        tool_vars.threshold.apply_at(ix, iy, src_grid.plane(c), &tool_vars.border, &src_grid.channel_map(c), otsu_value)
+                    }).collect()
+                } else {
+                    self.values_at(tool_vars, src_grid, ix, iy)
+                };
+                Tool::show_result(ui, pressed, dst_grid, dst_view, ix, iy, values);
+            },
+            Tool::Gradient => {
+                let (ix, iy) = (ix as i32, iy as i32);
+                let taps: Vec<_> = (-1..=1).flat_map(|y| (-1..=1).map(move |x| (ix + x, iy + y))).collect();
+                src_view.draw_outline_clamped(ui, src_grid, ix - 1, iy - 1, ix + 1, iy + 1);
+                Tool::draw_border_taps(ui, &tool_vars.border, src_grid, src_view, taps.into_iter());
+                dst_view.draw_outline_clamped(ui, dst_grid, ix, iy, ix, iy);
+                let values = self.values_at(tool_vars, src_grid, ix, iy);
+                Tool::show_result(ui, pressed, dst_grid, dst_view, ix, iy, values);
+                // arrow in the direction of the gradient, its length growing with the magnitude.
+                // for rgb grids, that's the gradient of the luma
+                if let Some((gx, gy)) = tool_vars.gradient.pair.luma_sums(src_grid, ix, iy, &tool_vars.border, tool_vars.luma) {
+                    let len = (gx * gx + gy * gy).sqrt();
+                    if len > 0.0 {
+                        let center = src_view.cell_center(ix, iy);
+                        let dir = egui::vec2(gx, -gy) / len * (10.0 + 30.0 * (len / 255.0).min(1.0));
+                        ui.painter().arrow(center, dir, egui::Stroke::new(2.0_f32, Color32::from_rgb(220, 30, 30)));
                     }
                 }
             },
+            Tool::Canny => {
+                let vars = &tool_vars.canny;
+                let reach = vars.stage.reach(vars.radius);
+                let (x, y) = (ix as i32, iy as i32);
+                src_view.draw_outline_clamped(ui, src_grid, x - reach, y - reach, x + reach, y + reach);
+                dst_view.draw_outline_clamped(ui, dst_grid, x, y, x, y);
+                let values = canny_cache.values_at(vars.stage, ix, iy);
+                Tool::show_result(ui, pressed, dst_grid, dst_view, x, y, values);
+            },
+            // every pixel of the result depends on the whole image, the filter is edited in the spectrum
+            Tool::Frequency => {},
         }
     }
 
-    fn bool_op(ix: i32, iy: i32, tool_vars: &ToolVars, src_grid: &PixGrid) -> u8 {
-        // for erosion, we start with true and only stay true if all the values are
-        // true, for dilation, we start with false and go true if any of the values is
-        // true
-        let mut b = !tool_vars.boolean_dilation;
-        let threshold = 127;
-        for y_off in -1..=1 {
-            for x_off in -1..=1 {
-                if tool_vars.boolean_mask[(y_off + 1) as usize][(x_off + 1) as usize] {
-                    let s_val = src_grid.get_clamped(ix + x_off, iy + y_off);
-                    if tool_vars.boolean_dilation {
-                        b |= s_val > threshold;
-                    } else {
-                        b &= s_val > threshold;
-                    }
-                }
-            }
+    /// the result of the tool at a pixel of a single channel, which is shown with the given mapping.
+    /// None means the pixel is skipped because of the border mode.
+    fn value_at(&self, tool_vars: &ToolVars, img: &ImageBuf<f32>, map: &DisplayMap, ix: i32, iy: i32) -> Option<f32> {
+        let border = &tool_vars.border;
+        match self {
+            Tool::Cpy => Some(img.get_clamped(ix, iy)),
+            Tool::Conv => tool_vars.conv.apply_at(img, ix, iy, border),
+            Tool::Boolean => morphology::boolean_at(
+                img, ix, iy, &tool_vars.boolean_mask, tool_vars.boolean_dilation, tool_vars.boolean_threshold as f32, border),
+            Tool::Rank => morphology::rank_at(img, ix, iy, &tool_vars.rank.mask, tool_vars.rank.percentile(), border),
+            Tool::Morph => {
+                let morph = &tool_vars.morph;
+                morphology::apply_at(morph.op, img, ix, iy, &morph.se, border)
+            },
+            Tool::Lut => Some(tool_vars.point_op.map(map.apply(img.get(ix as u32, iy as u32))) as f32),
+            Tool::Gradient => {
+                let vars = &tool_vars.gradient;
+                vars.pair.sums(img, ix, iy, border).map(|(gx, gy)| vars.output(gx, gy))
+            },
+            // the pen paints the source, and the frequency filter only works on the whole image.
+            // canny and otsu's threshold depend on the whole image, the hovered pixel is read
+            // from `CannyCache` and thresholded with `OtsuCache` instead
+            Tool::Pen | Tool::Frequency | Tool::Canny | Tool::Threshold => None,
         }
-        if b {255} else {0}
     }
 
-    fn convolution(ix: u32, iy: u32, conv: &Convolution, src_grid: &PixGrid) -> u8 {
-        let mut sum = 0.0;
-        if conv.zero_centered {
-            sum = 127.0
-        }
-        for y_offset in conv.up..=conv.down {
-            for x_offset in conv.left..=conv.right {
-                let x_conv_idx = (x_offset - conv.left) as usize;
-                let y_conv_idx = (y_offset - conv.up) as usize;
-                sum += conv.mask[y_conv_idx][x_conv_idx] *
-                    src_grid.get_clamped(ix as i32 + x_offset, iy as i32 + y_offset) as f32;
+    /// `value_at` for every channel of the source, which are processed independently
+    fn values_at(&self, tool_vars: &ToolVars, src_grid: &PixGrid, ix: i32, iy: i32) -> Option<Vec<f32>> {
+        (0..src_grid.channel_count())
+            .map(|c| self.value_at(tool_vars, src_grid.plane(c), &src_grid.channel_map(c), ix, iy))
+            .collect()
+    }
+
+    /// draw the taps of a neighbourhood that lie outside of the source image,
+    /// with the value the border mode gives them
+    fn draw_border_taps(ui: &mut egui::Ui, border: &Border, src_grid: &PixGrid, src_view: &GridView, taps: impl Iterator<Item = (i32, i32)>) {
+        let map = src_grid.display_map();
+        for (x, y) in taps {
+            if !src_grid.in_bounds(x, y) {
+                let px = match src_grid.resolve_border(x, y, border) {
+                    BorderTap::Pixel(x, y) => Some(src_grid.pixel(x, y)),
+                    BorderTap::Constant(c) => Some([c as f32; 3]),
+                    BorderTap::Skip => None,
+                };
+                src_view.draw_cell_at_idx(ui, src_grid, &map, x, y, px);
             }
         }
-        sum.clamp(0.0, 255.0) as u8
     }
 
-    fn apply_to_whole_image(&self, tool_vars: &ToolVars, src_grid: &mut PixGrid, dst_grid: &mut PixGrid) {
-        match self {
-            Tool::Pen => {
-                src_grid.reset_to_color(tool_vars.pen_color);
-            },
-            Tool::Cpy => {
-                for iy in 0..src_grid.height() {
-                    for ix in 0..src_grid.width() {
-                        let color = src_grid.get(ix, iy);
-                        dst_grid.try_set(ix as i32, iy as i32, color);
-                    }
+    /// preview the result of a neighbourhood operation in the target grid and set it while pressed.
+    /// there is one value per channel, None means the pixel is skipped because of the border mode.
+    fn show_result(ui: &mut egui::Ui, pressed: bool, dst_grid: &mut PixGrid, dst_view: &GridView, ix: i32, iy: i32, values: Option<Vec<f32>>) {
+        let map = dst_grid.display_map();
+        match values {
+            Some(values) => {
+                let px = std::array::from_fn(|c| values[c.min(values.len() - 1)]);
+                dst_view.try_draw_pixel_at_idx(ui, dst_grid, &map, ix, iy, px);
+                if pressed {
+                    dst_grid.try_set_pixel(ix, iy, &values);
                 }
             },
-            Tool::Conv => {
-                for iy in 0..src_grid.height() {
-                    for ix in 0..src_grid.width() {
-                        let color = Tool::convolution(ix, iy, &tool_vars.conv, &src_grid);
-                        dst_grid.try_set(ix as i32, iy as i32, color);
-                    }
+            None => {
+                if dst_grid.in_bounds(ix, iy) {
+                    dst_view.draw_cell_at_idx(ui, dst_grid, &map, ix, iy, None);
                 }
             },
-            Tool::Boolean => {
-                for iy in 0..src_grid.height() {
-                    for ix in 0..src_grid.width() {
-                        let color = Tool::bool_op(ix as i32, iy as i32, &tool_vars, &src_grid);
-                        dst_grid.try_set(ix as i32, iy as i32, color);
+        }
+    }
+
+    pub fn apply_to_whole_image(&self, tool_vars: &ToolVars, src_grid: &mut PixGrid, dst_grid: &mut PixGrid) {
+        if *self == Tool::Pen {
+            src_grid.reset_to_pixel(&tool_vars.pen_values(src_grid.is_color()));
+            return;
+        }
+        // the target gets the channels of the source, rgb grids are processed channel by channel
+        dst_grid.set_color(src_grid.is_color(), tool_vars.luma);
+        for c in 0..src_grid.channel_count() {
+            let mut dst = dst_grid.plane(c).clone();
+            self.apply_to_channel(tool_vars, src_grid.plane(c), &src_grid.channel_map(c), &mut dst);
+            dst_grid.set_plane(c, dst);
+        }
+    }
+
+    /// apply the tool to every pixel of a single channel. pixels the tool skips keep their value.
+    fn apply_to_channel(&self, tool_vars: &ToolVars, src: &ImageBuf<f32>, map: &DisplayMap, dst: &mut ImageBuf<f32>) {
+        let border = &tool_vars.border;
+        let mut set_each = |f: &dyn Fn(i32, i32) -> Option<f32>| {
+            for iy in 0..src.height() {
+                for ix in 0..src.width() {
+                    if let Some(color) = f(ix as i32, iy as i32) {
+                        dst.set(ix, iy, color);
                     }
                 }
+            }
+        };
+        match self {
+            // painting doesn't have a target, see `apply_to_whole_image`
+            Tool::Pen => {},
+            Tool::Canny => {
+                let vars = &tool_vars.canny;
+                let images = CannyImages::new(vars, src, border);
+                set_each(&|ix, iy| images.value(vars.stage, ix as u32, iy as u32));
+            },
+            Tool::Frequency => {
+                *dst = tool_vars.fourier.apply(src);
+            },
+            Tool::Threshold => {
+                let otsu = if tool_vars.threshold.mode == ThresholdMode::Otsu { threshold::otsu(src, map) } else { 0 };
+                set_each(&|ix, iy| tool_vars.threshold.apply_at(ix, iy, src, border, map, otsu));
+            },
+            Tool::Morph => {
+                let morph = &tool_vars.morph;
+                let result = morphology::apply_whole(morph.op, src, &morph.se, border);
+                set_each(&|ix, iy| result[iy as usize][ix as usize]);
+            },
+            // the others only look at a neighbourhood, which `value_at` handles
+            _ => set_each(&|ix, iy| self.value_at(tool_vars, src, map, ix, iy)),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+enum ResizeMode {
+    /// keep the content at the top left, cut it off or repeat the edge pixels
+    Crop,
+    /// keep the content at the top left, fill new pixels with a color
+    Pad,
+    /// scale the content to the new size
+    Resample,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+struct ResizeSettings {
+    mode: ResizeMode,
+    pad_color: u8,
+    resampling: Resampling,
+}
+
+impl Default for ResizeSettings {
+    fn default() -> Self {
+        Self { mode: ResizeMode::Pad, pad_color: 180, resampling: Resampling::Bilinear }
+    }
+}
+
+impl ResizeSettings {
+    /// set grid to base, resized to width x height
+    fn resize(&self, base: &PixGrid, grid: &mut PixGrid, width: u32, height: u32) {
+        match self.mode {
+            ResizeMode::Crop => {
+                grid.copy_pixels_from(base);
+                grid.resize(width, height, None);
+            },
+            ResizeMode::Pad => {
+                grid.copy_pixels_from(base);
+                grid.resize(width, height, Some(self.pad_color));
+            },
+            ResizeMode::Resample => {
+                grid.set_planes(base.planes().iter().map(|plane| import::resample(
+                    base.width(), base.height(), |x, y| plane.get(x, y),
+                    width, height, self.resampling)).collect());
             },
         }
     }
 }
 
-struct Convolution {
-    zero_centered: bool,
-    left: i32,
-    right: i32,
-    up: i32,
-    down: i32,
-    mask: Vec<Vec<f32>>,
+/// checkboxes for the pixels of a mask. returns whether anything changed.
+fn mask_editor(ui: &mut egui::Ui, mask: &mut Mask) -> bool {
+    ui.horizontal(|ui| {
+        let mut changed = false;
+        let size = mask.mask.len();
+        for ix in 0..size {
+            ui.vertical(|ui| {
+                for iy in 0..size {
+                    changed |= ui.checkbox(&mut mask.mask[iy][ix], "").changed();
+                }
+            });
+        }
+        changed
+    }).inner
+}
+
+fn resampling_selector(ui: &mut egui::Ui, resampling: &mut Resampling) {
+    ui.selectable_value(resampling, Resampling::Nearest, "Nearest");
+    ui.selectable_value(resampling, Resampling::Box, "Box");
+    ui.selectable_value(resampling, Resampling::Bilinear, "Bilinear");
+}
+
+/// parameters of the histogram based point operations
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+struct HistogramVars {
+    /// percentiles that contrast stretching maps to 0 and 255
+    stretch_low: f32,
+    stretch_high: f32,
+}
+
+impl Default for HistogramVars {
+    fn default() -> Self {
+        Self { stretch_low: 2.0, stretch_high: 98.0 }
+    }
+}
+
+/// settings of the spectrum view
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+struct SpectrumSettings {
+    view: SpectrumView,
+    /// show the zero frequency in the middle
+    centered: bool,
 }
 
+impl Default for SpectrumSettings {
+    fn default() -> Self {
+        Self { view: SpectrumView::Magnitude, centered: true }
+    }
+}
+
+fn default_spectrum_grid() -> PixGrid {
+    PixGrid::new(20, 12, 0)
+}
 
+/// version of the persisted state. bump it when the meaning of a stored field changes
+/// and convert the old data in `ImgProcDemo::migrate`.
+const STATE_VERSION: u32 = 2;
+
+// missing fields are filled in from Default, so states saved by older versions still load
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
 pub struct ImgProcDemo {
+    // states from before the version was introduced don't have it, so they get 0
+    #[serde(default)]
+    state_version: u32,
     src_grid: PixGrid,
     dst_grid: PixGrid,
+    src_view: GridView,
+    dst_view: GridView,
     tool: Tool,
     tool_vars: ToolVars,
+    #[serde(skip)]
+    history: History,
+    import: ImportSettings,
+    #[serde(skip)]
+    loader: ImageLoader,
+    #[serde(skip)]
+    import_error: Option<String>,
+    export: ExportSettings,
+    #[serde(skip)]
+    export_error: Option<String>,
+    resize: ResizeSettings,
+    // the grids from before the size is being changed, so changing it back and forth
+    // doesn't lose anything until the size widgets are released
+    #[serde(skip)]
+    resize_base: Option<(PixGrid, PixGrid)>,
+    histogram: HistogramVars,
+    spectrum: SpectrumSettings,
+    // shows the spectrum, its pixels are recomputed when the source, the filter or the view change
+    #[serde(skip, default = "default_spectrum_grid")]
+    spectrum_grid: PixGrid,
+    /// follows the view of the source grid
+    #[serde(skip)]
+    spectrum_view: GridView,
+    #[serde(skip)]
+    spectrum_cache: SpectrumCache,
+    /// show the channels of an rgb source as separate gray grids
+    split_channels: bool,
+    #[serde(skip)]
+    channel_views: [GridView; 3],
+    #[serde(skip)]
+    canny_cache: CannyCache,
+    #[serde(skip)]
+    otsu_cache: OtsuCache,
+    #[serde(skip)]
+    src_histogram: HistogramCache,
+    #[serde(skip)]
+    dst_histogram: HistogramCache,
+    pipeline: Pipeline,
+    /// show the node graph instead of the grids and tools
+    graph_mode: bool,
+    graph: Graph,
+    #[serde(skip)]
+    graph_editor: GraphEditor,
 }
 
-impl ImgProcDemo {
-    pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
+impl Default for ImgProcDemo {
+    fn default() -> Self {
         let mut s = Self {
-            src_grid: PixGrid::new(20, 12, 180, 16),
-            dst_grid: PixGrid::new(20, 12, 180, 16),
+            state_version: STATE_VERSION,
+            src_grid: PixGrid::new(20, 12, 180),
+            dst_grid: PixGrid::new(20, 12, 180),
+            src_view: GridView::new(16),
+            dst_view: GridView::new(16),
             tool: Tool::Pen,
             tool_vars: ToolVars {
                 pen_color: 50,
-                conv: Convolution {
-                    zero_centered: false, left: -1, right: 1, up: -1, down: 1,
-                    mask: vec![ // binomial filter
-                        vec![1.0/16.0, 2.0/16.0, 1.0/16.0],
-                        vec![2.0/16.0, 4.0/16.0, 2.0/16.0],
-                        vec![1.0/16.0, 2.0/16.0, 1.0/16.0],
-                    ],
-                },
+                pen_rgb: default_pen_rgb(),
+                // the first preset is the binomial filter
+                conv: (PRESETS[0].build)(&PresetParams::default()),
                 boolean_mask: [
                     [false, true, false],
                     [true, true, true],
                     [false, true, false]
                 ],
                 boolean_dilation: true,
+                boolean_threshold: default_boolean_threshold(),
+                border: Border::default(),
+                preset: 0,
+                preset_params: PresetParams::default(),
+                rank: RankFilter::default(),
+                morph: Morphology::default(),
+                threshold: Thresholding::default(),
+                point_op: PointOp::default(),
+                gradient: Gradient::default(),
+                canny: Canny::default(),
+                fourier: FourierFilter::default(),
+                luma: LumaWeights::default(),
+                conversion: Conversion::default(),
+                show_weights: false,
+                revision: 0,
             },
+            history: History::default(),
+            import: ImportSettings::default(),
+            loader: ImageLoader::default(),
+            import_error: None,
+            export: ExportSettings::default(),
+            export_error: None,
+            resize: ResizeSettings::default(),
+            resize_base: None,
+            histogram: HistogramVars::default(),
+            spectrum: SpectrumSettings::default(),
+            spectrum_grid: default_spectrum_grid(),
+            spectrum_view: GridView::default(),
+            spectrum_cache: SpectrumCache::default(),
+            split_channels: false,
+            channel_views: Default::default(),
+            canny_cache: CannyCache::default(),
+            otsu_cache: OtsuCache::default(),
+            src_histogram: HistogramCache::default(),
+            dst_histogram: HistogramCache::default(),
+            pipeline: Pipeline::default(),
+            graph_mode: false,
+            graph: Graph::default(),
+            graph_editor: GraphEditor::default(),
         };
-        s.src_grid.try_set(5, 2, 0);
+        s.src_grid.try_set(5, 2, 0.0);
         s
     }
+}
 
-    fn conv_binomial(&mut self) {
-        let conv = &mut self.tool_vars.conv;
-        conv.left = -1;
-        conv.right = 1;
-        conv.up = -1;
-        conv.down = 1;
-        conv.zero_centered = false;
-        conv.mask = vec![ // binomial filter
-                        vec![1.0/16.0, 2.0/16.0, 1.0/16.0],
-                        vec![2.0/16.0, 4.0/16.0, 2.0/16.0],
-                        vec![1.0/16.0, 2.0/16.0, 1.0/16.0],
-        ];
-    }
-    fn conv_v_sobel(&mut self) {
-        let conv = &mut self.tool_vars.conv;
-        conv.left = -1;
-        conv.right = 1;
-        conv.up = -1;
-        conv.down = 1;
-        conv.zero_centered = true;
-        conv.mask = vec![ // binomial filter
-                        vec![-1.0, 0.0, 1.0],
-                        vec![-2.0, 0.0, 2.0],
-                        vec![-1.0, 0.0, 1.0],
-        ];
-    }
-    fn conv_h_sobel(&mut self) {
-        let conv = &mut self.tool_vars.conv;
-        conv.left = -1;
-        conv.right = 1;
-        conv.up = -1;
-        conv.down = 1;
-        conv.zero_centered = true;
-        conv.mask = vec![ // binomial filter
-                        vec![1.0, 2.0, 1.0],
-                        vec![0.0, 0.0, 0.0],
-                        vec![-1.0, -2.0, -1.0],
-        ];
+impl ImgProcDemo {
+    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        // restore the previous session, if there is one
+        if let Some(storage) = cc.storage {
+            if let Some(state) = eframe::get_value::<ImgProcDemo>(storage, eframe::APP_KEY) {
+                if let Some(state) = state.migrate() {
+                    return state;
+                }
+                log::warn!("discarding inconsistent saved state");
+            }
+        }
+        Default::default()
     }
 
+    /// bring a freshly loaded state up to the current version.
+    /// returns None if the state can't be used.
+    fn migrate(mut self) -> Option<Self> {
+        // apart from that, versions only differ by added fields, which serde already filled in.
+        if self.state_version < 2 {
+            // the grids store their pixels as channels
+            self.src_grid.migrate();
+            self.dst_grid.migrate();
+        }
+        self.state_version = STATE_VERSION;
+
+        // the controls keep these in range, but the stored state could hold anything
+        self.tool_vars.preset_params.clamp();
+        self.import.width = self.import.width.clamp(1, MAX_SIZE);
+        self.import.height = self.import.height.clamp(1, MAX_SIZE);
+        self.export.scale = self.export.scale.clamp(1, export::MAX_SCALE);
+        if self.tool_vars.is_consistent() && self.pipeline.is_consistent() && self.graph.validate().is_ok()
+            && self.src_grid.is_consistent() && self.dst_grid.is_consistent()
+            && self.src_grid.same_shape(&self.dst_grid) {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+    /// run an action that changes the grids as a single undo step
+    fn edit(&mut self, action: impl FnOnce(&mut Self)) {
+        self.history.begin_edit(&self.src_grid, &self.dst_grid);
+        action(self);
+        self.history.end_edit(&self.src_grid, &self.dst_grid);
+    }
+
+    /// put a loaded image file into the source grid. the target grid gets the same size.
+    /// rgb grids load the colors, gray ones the luma.
+    fn import_image(&mut self, file: Result<Vec<u8>, String>) {
+        match file.and_then(|bytes| self.import.load(&bytes, self.src_grid.is_color())) {
+            Ok(channels) => {
+                let blank = vec![ImageBuf::new(channels[0].width(), channels[0].height(), 180.0); channels.len()];
+                self.edit(|s| {
+                    s.src_grid.set_planes(channels);
+                    s.dst_grid.set_planes(blank);
+                });
+                self.import_error = None;
+            },
+            Err(e) => {
+                log::warn!("failed to import image: {}", e);
+                self.import_error = Some(e);
+            },
+        }
+    }
 
     fn pen_row(&mut self, ui: &mut egui::Ui) {
         ui.horizontal(|ui| {
             ui.selectable_value(&mut self.tool, Tool::Pen, "Pen");
+            if self.src_grid.is_color() {
+                if ui.color_edit_button_srgb(&mut self.tool_vars.pen_rgb).changed() {
+                    self.tool_vars.touch();
+                    self.tool = Tool::Pen;
+                }
+                return;
+            }
             let mut color_proxy = self.tool_vars.pen_color as f32;
             let slider = egui::Slider::new(&mut color_proxy, 0.0..=255.0).text("Color").clamp_to_range(true);
             if ui.add(slider).changed() {
                 self.tool_vars.pen_color = color_proxy.round() as u8;
+                self.tool_vars.touch();
                 self.tool = Tool::Pen;
             }
         });
@@ -253,107 +787,996 @@ impl ImgProcDemo {
     fn conv_row(&mut self, ui: &mut egui::Ui) {
         ui.horizontal(|ui| {
             ui.selectable_value(&mut self.tool, Tool::Conv, "Convolution");
+            let mut edited = false;
 
             let conv = &mut self.tool_vars.conv;
+            // extents, as distances from the center. the center itself always stays in the kernel
+            ui.vertical(|ui| {
+                let mut left = -conv.left;
+                let mut right = conv.right;
+                let mut up = -conv.up;
+                let mut down = conv.down;
+                let mut changed = false;
+                for (val, name) in [(&mut left, "Left: "), (&mut right, "Right: "), (&mut up, "Up: "), (&mut down, "Down: ")] {
+                    changed |= ui.add(egui::DragValue::new(val).clamp_range(0..=MAX_CONV_EXTENT).prefix(name)).changed();
+                }
+                if changed {
+                    conv.set_extents(-left, right, -up, down);
+                    edited = true;
+                }
+            });
+
             ui.horizontal(|ui| {
+                // keep wide kernels from pushing everything else out of the window
+                let columns = (conv.right - conv.left + 1) as f32;
+                ui.spacing_mut().slider_width = (300.0 / columns).clamp(40.0, 100.0);
+                // some presets have larger weights, their sliders shouldn't cut them off
+                let limit = conv.mask.iter().flatten().fold(2.0_f32, |m, v| m.max(v.abs().ceil()));
                 for ix in conv.left..=conv.right {
                     ui.vertical(|ui| {
                         for iy in conv.up..=conv.down {
                             let slider = egui::Slider::new(
                                 &mut conv.mask[(iy - conv.up) as usize][(ix - conv.left) as usize],
-                                -2.0..=2.0);
+                                -limit..=limit);
                             if ui.add(slider).changed() {
-                                self.tool = Tool::Conv;
+                                edited = true;
                             }
                         }
                     });
                 }
             });
 
-            if ui.toggle_value(&mut conv.zero_centered, "Zero-centered").changed() {
-                self.tool = Tool::Conv;
-            }
+            ui.vertical(|ui| {
+                if ui.toggle_value(&mut conv.zero_centered, "Zero-centered").changed() {
+                    edited = true;
+                }
+                ui.toggle_value(&mut self.tool_vars.show_weights, "Show weights")
+                    .on_hover_text("Write the weights into the hovered neighbourhood");
+            });
 
             ui.vertical(|ui| {
-                ui.label("Example filters:");
-                if ui.button("Binomial filter").clicked() {
-                    self.conv_binomial();
-                    self.tool = Tool::Conv;
+                let vars = &mut self.tool_vars;
+                let mut changed = false;
+                egui::ComboBox::from_label("Preset")
+                    .selected_text(PRESETS[vars.preset].name)
+                    .show_ui(ui, |ui| {
+                        for (i, preset) in PRESETS.iter().enumerate() {
+                            // choosing the current preset again resets the kernel to it
+                            changed |= ui.selectable_value(&mut vars.preset, i, preset.name).clicked();
+                        }
+                    });
+
+                let preset = &PRESETS[vars.preset];
+                let params = &mut vars.preset_params;
+                if preset.params.contains(&Param::Radius) {
+                    let slider = egui::Slider::new(&mut params.radius, 0..=MAX_CONV_EXTENT).text("Radius");
+                    changed |= ui.add(slider).changed();
+                }
+                if preset.params.contains(&Param::Sigma) {
+                    let slider = egui::Slider::new(&mut params.sigma, 0.2..=4.0).text("Sigma");
+                    changed |= ui.add(slider).changed();
                 }
-                if ui.button("Vertical Sobel").clicked() {
-                    self.conv_v_sobel();
-                    self.tool = Tool::Conv;
+                if preset.params.contains(&Param::Amount) {
+                    let slider = egui::Slider::new(&mut params.amount, 0.0..=4.0).text("Amount");
+                    changed |= ui.add(slider).changed();
                 }
-                if ui.button("Horizontal Sobel").clicked() {
-                    self.conv_h_sobel();
-                    self.tool = Tool::Conv;
+                if preset.params.contains(&Param::Angle) {
+                    let slider = egui::Slider::new(&mut params.angle, 0.0..=180.0).text("Angle").suffix("°");
+                    changed |= ui.add(slider).changed();
+                }
+                if changed {
+                    vars.conv = (preset.build)(params);
+                    edited = true;
                 }
             });
+            if edited {
+                self.tool_vars.touch();
+                self.tool = Tool::Conv;
+            }
         });
     }
 
     fn bool_row(&mut self, ui: &mut egui::Ui) {
         ui.horizontal(|ui| {
             ui.selectable_value(&mut self.tool, Tool::Boolean, "Boolean operation");
+            let mut changed = false;
             if ui.toggle_value(&mut self.tool_vars.boolean_dilation, "Dilation").changed() {
-                self.tool = Tool::Boolean;
+                changed = true;
             }
             let mut b_proxy = !self.tool_vars.boolean_dilation;
             if ui.toggle_value(&mut b_proxy, "Erosion").changed() {
                 self.tool_vars.boolean_dilation = !b_proxy;
-                self.tool = Tool::Boolean;
+                changed = true;
             }
 
             for ix in 0..=2 {
                 ui.vertical(|ui| {
                     for iy in 0..=2 {
                         if ui.checkbox(&mut self.tool_vars.boolean_mask[iy][ix], "").changed() {
-                            self.tool = Tool::Boolean;
+                            changed = true;
                         }
                     }
                 });
             }
+
+            let slider = egui::Slider::new(&mut self.tool_vars.boolean_threshold, 0..=morphology::MAX_THRESHOLD).text("Threshold");
+            if ui.add(slider).on_hover_text("Values above the threshold count as true").changed() {
+                changed = true;
+            }
+            if changed {
+                self.tool_vars.touch();
+                self.tool = Tool::Boolean;
+            }
+        });
+    }
+
+    fn threshold_row(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.tool, Tool::Threshold, "Threshold");
+            let threshold = &mut self.tool_vars.threshold;
+            let mut changed = false;
+            changed |= ui.selectable_value(&mut threshold.mode, ThresholdMode::Global, "Global").changed();
+            changed |= ui.selectable_value(&mut threshold.mode, ThresholdMode::Otsu, "Otsu").changed();
+            changed |= ui.selectable_value(&mut threshold.mode, ThresholdMode::AdaptiveMean, "Adaptive mean").changed();
+            changed |= ui.selectable_value(&mut threshold.mode, ThresholdMode::AdaptiveGaussian, "Adaptive Gaussian").changed();
+            match threshold.mode {
+                ThresholdMode::Global => {
+                    changed |= ui.add(egui::Slider::new(&mut threshold.value, 0..=254).text("Threshold")).changed();
+                },
+                ThresholdMode::Otsu => {
+                    // rgb grids get a threshold per channel
+                    self.otsu_cache.update(&self.src_grid);
+                    let thresholds: Vec<String> = self.otsu_cache.thresholds().iter().map(u8::to_string).collect();
+                    ui.label(format!("Threshold: {}", thresholds.join(", ")))
+                        .on_hover_text("A displayed gray level, the pixels are compared as they are shown");
+                },
+                ThresholdMode::AdaptiveMean | ThresholdMode::AdaptiveGaussian => {
+                    let radius = egui::DragValue::new(&mut threshold.radius).clamp_range(1..=MAX_ADAPTIVE_RADIUS).prefix("Radius: ");
+                    changed |= ui.add(radius).changed();
+                    changed |= ui.add(egui::Slider::new(&mut threshold.offset, -50.0..=50.0).text("Offset"))
+                        .on_hover_text("Subtracted from the local mean").changed();
+                },
+            }
+            if changed {
+                self.tool_vars.touch();
+                self.tool = Tool::Threshold;
+            }
+        });
+    }
+
+    fn rank_row(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.tool, Tool::Rank, "Rank filter");
+            let rank = &mut self.tool_vars.rank;
+            let mut changed = false;
+            ui.vertical(|ui| {
+                ui.horizontal(|ui| {
+                    changed |= ui.selectable_value(&mut rank.op, RankOp::Median, "Median").changed();
+                    changed |= ui.selectable_value(&mut rank.op, RankOp::Min, "Min").changed();
+                    changed |= ui.selectable_value(&mut rank.op, RankOp::Max, "Max").changed();
+                    changed |= ui.selectable_value(&mut rank.op, RankOp::Percentile, "Percentile").changed();
+                });
+                if rank.op == RankOp::Percentile {
+                    changed |= ui.add(egui::Slider::new(&mut rank.percentile, 0.0..=100.0).suffix("%")).changed();
+                }
+                let mut radius = rank.mask.radius;
+                if ui.add(egui::DragValue::new(&mut radius).clamp_range(0..=MAX_CONV_EXTENT).prefix("Radius: ")).changed() {
+                    rank.mask.set_radius(radius);
+                    changed = true;
+                }
+            });
+            changed |= mask_editor(ui, &mut rank.mask);
+            if changed {
+                self.tool_vars.touch();
+                self.tool = Tool::Rank;
+            }
         });
     }
+
+    fn morph_row(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.tool, Tool::Morph, "Morphology");
+            let morph = &mut self.tool_vars.morph;
+            let mut changed = false;
+            ui.vertical(|ui| {
+                egui::ComboBox::from_id_source("morph_op")
+                    .selected_text(morph.op.name())
+                    .show_ui(ui, |ui| {
+                        for op in MorphOp::ALL {
+                            changed |= ui.selectable_value(&mut morph.op, op, op.name()).changed();
+                        }
+                    });
+
+                // picking a shape or changing its size overwrites the structuring element
+                let mut reshape = false;
+                ui.horizontal(|ui| {
+                    reshape |= ui.selectable_value(&mut morph.shape, MaskShape::Cross, "Cross").clicked();
+                    reshape |= ui.selectable_value(&mut morph.shape, MaskShape::Square, "Square").clicked();
+                    reshape |= ui.selectable_value(&mut morph.shape, MaskShape::Disk, "Disk").clicked();
+                    reshape |= ui.selectable_value(&mut morph.shape, MaskShape::Line, "Line").clicked();
+                });
+                let mut radius = morph.se.radius;
+                reshape |= ui.add(egui::DragValue::new(&mut radius).clamp_range(0..=MAX_CONV_EXTENT).prefix("Radius: ")).changed();
+                if morph.shape == MaskShape::Line {
+                    reshape |= ui.add(egui::Slider::new(&mut morph.angle, 0.0..=180.0).text("Angle").suffix("°")).changed();
+                }
+                if reshape {
+                    morph.se = Mask::from_shape(morph.shape, radius, morph.angle);
+                    changed = true;
+                }
+            });
+            changed |= mask_editor(ui, &mut morph.se);
+            if changed {
+                self.tool_vars.touch();
+                self.tool = Tool::Morph;
+            }
+        });
+    }
+
+    fn lut_row(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.tool, Tool::Lut, "Point operation");
+            let op = &mut self.tool_vars.point_op;
+            let mut changed = false;
+            ui.vertical(|ui| {
+                let mut preset_changed = false;
+                egui::ComboBox::from_id_source("lut_preset")
+                    .selected_text(op.preset.name())
+                    .show_ui(ui, |ui| {
+                        for preset in LutPreset::ALL {
+                            // choosing the current preset again resets the curve to it
+                            preset_changed |= ui.selectable_value(&mut op.preset, preset, preset.name()).clicked();
+                        }
+                    });
+                match op.preset {
+                    LutPreset::Gamma => {
+                        preset_changed |= ui.add(egui::Slider::new(&mut op.gamma, 0.1..=5.0).logarithmic(true).text("Gamma")).changed();
+                    },
+                    LutPreset::BrightnessContrast => {
+                        preset_changed |= ui.add(egui::Slider::new(&mut op.brightness, -255.0..=255.0).text("Brightness")).changed();
+                        preset_changed |= ui.add(egui::Slider::new(&mut op.contrast, 0.0..=5.0).text("Contrast")).changed();
+                    },
+                    LutPreset::Posterize => {
+                        preset_changed |= ui.add(egui::Slider::new(&mut op.levels, 2..=16).text("Levels")).changed();
+                    },
+                    LutPreset::BitPlane => {
+                        preset_changed |= ui.add(egui::Slider::new(&mut op.bit, 0..=7).text("Bit")).changed();
+                    },
+                    LutPreset::Identity | LutPreset::Invert | LutPreset::Log => {},
+                }
+                if preset_changed {
+                    op.apply_preset();
+                }
+                changed |= preset_changed;
+                ui.label("Drag points to edit the curve,\nclick to add one, right click to remove one.");
+            });
+            changed |= op.curve_editor(ui, 160.0);
+            if changed {
+                self.tool_vars.touch();
+                self.tool = Tool::Lut;
+            }
+        });
+    }
+
+    fn gradient_row(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.tool, Tool::Gradient, "Gradient");
+            let gradient = &mut self.tool_vars.gradient;
+            let mut changed = false;
+            ui.vertical(|ui| {
+                ui.horizontal(|ui| {
+                    for pair in DerivativePair::ALL {
+                        changed |= ui.selectable_value(&mut gradient.pair, pair, pair.name()).changed();
+                    }
+                });
+                egui::ComboBox::from_id_source("gradient_output")
+                    .selected_text(gradient.output.name())
+                    .show_ui(ui, |ui| {
+                        for output in GradientOutput::ALL {
+                            changed |= ui.selectable_value(&mut gradient.output, output, output.name()).changed();
+                        }
+                    });
+                if gradient.output != GradientOutput::Orientation {
+                    changed |= ui.add(egui::Slider::new(&mut gradient.scale, 0.1..=10.0).logarithmic(true).text("Scale")).changed();
+                }
+                // the partial derivatives of the hovered pixel, of the luma for rgb grids
+                if self.tool == Tool::Gradient {
+                    if let Some((ix, iy)) = self.src_view.hovered_idx() {
+                        match gradient.pair.luma_sums(&self.src_grid, ix as i32, iy as i32, &self.tool_vars.border, self.tool_vars.luma) {
+                            Some((gx, gy)) => ui.label(format!(
+                                "Gx: {:.1}  Gy: {:.1}  Angle: {:.1}°", gx, gy, gradient::angle(gx, gy))),
+                            None => ui.label("Skipped at the border"),
+                        };
+                    }
+                }
+            });
+            if changed {
+                self.tool_vars.touch();
+                self.tool = Tool::Gradient;
+            }
+        });
+    }
+
+    fn canny_row(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.tool, Tool::Canny, "Canny");
+            let canny = &mut self.tool_vars.canny;
+            let mut changed = false;
+            ui.vertical(|ui| {
+                ui.horizontal(|ui| {
+                    changed |= ui.add(egui::DragValue::new(&mut canny.radius).clamp_range(0..=MAX_CONV_EXTENT).prefix("Radius: ")).changed();
+                    changed |= ui.add(egui::Slider::new(&mut canny.sigma, 0.2..=4.0).text("Sigma")).changed();
+                });
+                ui.horizontal(|ui| {
+                    for pair in DerivativePair::ALL {
+                        changed |= ui.selectable_value(&mut canny.pair, pair, pair.name()).changed();
+                    }
+                });
+                let low = ui.add(egui::Slider::new(&mut canny.low, 0.0..=255.0).text("Low"));
+                let high = ui.add(egui::Slider::new(&mut canny.high, 0.0..=255.0).text("High"));
+                // keep the thresholds in order, moving the one that isn't dragged
+                if low.changed() {
+                    canny.high = canny.high.max(canny.low);
+                }
+                if high.changed() {
+                    canny.low = canny.low.min(canny.high);
+                }
+                changed |= low.changed() || high.changed();
+                ui.horizontal(|ui| {
+                    if let Some(prev) = canny.stage.prev() {
+                        if ui.button("◀").on_hover_text(prev.name()).clicked() {
+                            canny.stage = prev;
+                            changed = true;
+                        }
+                    }
+                    egui::ComboBox::from_id_source("canny_stage")
+                        .selected_text(canny.stage.name())
+                        .show_ui(ui, |ui| {
+                            for stage in CannyStage::ALL {
+                                changed |= ui.selectable_value(&mut canny.stage, stage, stage.name()).changed();
+                            }
+                        });
+                    if let Some(next) = canny.stage.next() {
+                        if ui.button("▶").on_hover_text(next.name()).clicked() {
+                            canny.stage = next;
+                            changed = true;
+                        }
+                    }
+                });
+                // what each stage gives for the hovered pixel, of the luma for rgb grids
+                if self.tool == Tool::Canny {
+                    self.canny_cache.update(&self.src_grid, canny, &self.tool_vars.border, self.tool_vars.luma);
+                    if let Some(((x, y), images)) = self.src_view.hovered_idx().zip(self.canny_cache.luma()) {
+                        let mut text = String::new();
+                        if let Some(v) = images.smoothed.get(x, y) {
+                            text += &format!("Smoothed: {:.1}", v);
+                        }
+                        if let Some((gx, gy)) = images.gradient.get(x, y) {
+                            text += &format!("\nGx: {:.1}  Gy: {:.1}  Angle: {:.1}°", gx, gy, gradient::angle(gx, gy));
+                        }
+                        if let Some(m) = images.suppressed.get(x, y) {
+                            text += &format!("\nAfter suppression: {:.1}", m);
+                        }
+                        if let Some(c) = images.classes.get(x, y) {
+                            text += &format!("\nClass: {}", c.name());
+                        }
+                        if let Some(e) = images.edges.get(x, y) {
+                            text += if e { "\nEdge: yes" } else { "\nEdge: no" };
+                        }
+                        if text.is_empty() {
+                            text += "Skipped at the border";
+                        }
+                        ui.label(text);
+                    }
+                }
+            });
+            if changed {
+                self.tool_vars.touch();
+                self.tool = Tool::Canny;
+            }
+        });
+    }
+
+    fn frequency_row(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.tool, Tool::Frequency, "Frequency filter");
+            let filter = &mut self.tool_vars.fourier;
+            let mut changed = false;
+            ui.vertical(|ui| {
+                // changing the preset overwrites the mask
+                let mut preset_changed = false;
+                ui.horizontal(|ui| {
+                    preset_changed |= ui.selectable_value(&mut filter.shape, FilterShape::Ideal, "Ideal").clicked();
+                    preset_changed |= ui.selectable_value(&mut filter.shape, FilterShape::Butterworth, "Butterworth").clicked();
+                    preset_changed |= ui.selectable_value(&mut filter.shape, FilterShape::Gaussian, "Gaussian").clicked();
+                });
+                ui.horizontal(|ui| {
+                    preset_changed |= ui.selectable_value(&mut filter.band, FilterBand::Low, "Low-pass").clicked();
+                    preset_changed |= ui.selectable_value(&mut filter.band, FilterBand::High, "High-pass").clicked();
+                    preset_changed |= ui.selectable_value(&mut filter.band, FilterBand::Band, "Band-pass").clicked();
+                });
+                preset_changed |= ui.add(egui::Slider::new(&mut filter.cutoff, 0.0..=0.71).text("Cutoff"))
+                    .on_hover_text("In cycles per pixel, 0.5 is the highest frequency along an axis").changed();
+                if filter.band == FilterBand::Band {
+                    preset_changed |= ui.add(egui::Slider::new(&mut filter.width, 0.01..=0.5).text("Width")).changed();
+                }
+                if filter.shape == FilterShape::Butterworth {
+                    preset_changed |= ui.add(egui::Slider::new(&mut filter.order, 1..=10).text("Order")).changed();
+                }
+                if preset_changed {
+                    filter.apply_preset(self.src_grid.width(), self.src_grid.height());
+                }
+                changed |= preset_changed;
+                ui.horizontal(|ui| {
+                    if ui.button("Use convolution kernel")
+                        .on_hover_text("Gives the same result as the convolution with wrapping border handling")
+                        .clicked() {
+                        filter.set_from_kernel(&self.tool_vars.conv, self.src_grid.width(), self.src_grid.height());
+                        changed = true;
+                    }
+                    changed |= ui.toggle_value(&mut filter.zero_centered, "Zero-centered").changed();
+                });
+                ui.horizontal(|ui| {
+                    changed |= ui.add(egui::Slider::new(&mut filter.paint_gain, 0.0..=1.0).text("Paint gain")).changed();
+                    changed |= ui.add(egui::DragValue::new(&mut filter.brush_radius).clamp_range(0..=8).prefix("Brush: ")).changed();
+                });
+                ui.label("Paint into the spectrum to edit the filter.");
+            });
+            if changed {
+                self.tool_vars.touch();
+                self.tool = Tool::Frequency;
+            }
+        });
+    }
+
+    /// the spectrum of the source grid, of its luma for rgb grids. with the frequency filter, it can be painted into.
+    fn spectrum_section(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let settings = &mut self.spectrum;
+            ui.selectable_value(&mut settings.view, SpectrumView::Magnitude, "Magnitude");
+            ui.selectable_value(&mut settings.view, SpectrumView::Phase, "Phase");
+            ui.selectable_value(&mut settings.view, SpectrumView::Filter, "Filter");
+            ui.checkbox(&mut settings.centered, "Centered");
+        });
+        let (w, h) = (self.src_grid.width(), self.src_grid.height());
+        let filtering = self.tool == Tool::Frequency;
+        let filter = &mut self.tool_vars.fourier;
+        let shown_filter = (filtering || self.spectrum.view == SpectrumView::Filter).then_some(&*filter);
+        let displayed = self.spectrum_cache.display(
+            &self.src_grid, self.tool_vars.luma, shown_filter, self.spectrum.view, self.spectrum.centered);
+        if let Some(img) = displayed {
+            self.spectrum_grid.set_image(img);
+        }
+        self.spectrum_view.copy_view_from(&self.src_view);
+        self.spectrum_view.draw(ui, &self.spectrum_grid);
+        if self.spectrum_view.view_changed() {
+            self.src_view.copy_view_from(&self.spectrum_view);
+            self.dst_view.copy_view_from(&self.spectrum_view);
+        }
+
+        if let (true, Some((ix, iy))) = (filtering, self.spectrum_view.hovered_idx()) {
+            let r = filter.brush_radius;
+            let (x, y) = (ix as i32, iy as i32);
+            self.spectrum_view.draw_outline_clamped(ui, &self.spectrum_grid, x - r, y - r, x + r, y + r);
+            if self.spectrum_view.pressed() {
+                for py in (y - r).max(0)..=(y + r).min(h as i32 - 1) {
+                    for px in (x - r).max(0)..=(x + r).min(w as i32 - 1) {
+                        let (u, v) = fourier::freq_idx(px as u32, py as u32, w, h, self.spectrum.centered);
+                        filter.paint(u, v);
+                    }
+                }
+                self.tool_vars.touch();
+            }
+        }
+    }
+
+    fn border_row(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let border = &mut self.tool_vars.border;
+            let before = *border;
+            ui.label("Border handling:");
+            ui.selectable_value(&mut border.mode, BorderMode::Clamp, "Clamp")
+                .on_hover_text("Repeat the edge pixels");
+            ui.selectable_value(&mut border.mode, BorderMode::Constant, "Constant")
+                .on_hover_text("Use a fixed value outside of the image");
+            ui.selectable_value(&mut border.mode, BorderMode::Mirror, "Mirror")
+                .on_hover_text("Reflect the image at the edge pixels");
+            ui.selectable_value(&mut border.mode, BorderMode::Wrap, "Wrap")
+                .on_hover_text("Continue at the opposite edge, as if the image was periodic");
+            ui.selectable_value(&mut border.mode, BorderMode::Skip, "Skip")
+                .on_hover_text("Leave pixels unprocessed if their neighbourhood leaves the image");
+            if border.mode == BorderMode::Constant {
+                ui.add(egui::Slider::new(&mut border.constant, 0..=255).text("Value"));
+            }
+            if *border != before {
+                self.tool_vars.touch();
+            }
+        });
+    }
+
+    /// how the values of a grid are shown, and a button to turn them into the shown gray levels
+    fn display_row(&mut self, ui: &mut egui::Ui, target: bool) {
+        let mut quantize = false;
+        ui.horizontal(|ui| {
+            let (grid, view) = if target { (&mut self.dst_grid, &mut self.dst_view) } else { (&mut self.src_grid, &mut self.src_view) };
+            let (min, max) = grid.value_range();
+            let color = grid.is_color();
+            let display = &mut grid.display;
+            ui.selectable_value(&mut display.mode, DisplayMode::Clamp, "Clamp")
+                .on_hover_text("Cut off values outside of 0 to 255");
+            ui.selectable_value(&mut display.mode, DisplayMode::OffsetScale, "Offset + scale")
+                .on_hover_text("Show value × scale + offset");
+            ui.selectable_value(&mut display.mode, DisplayMode::Abs, "Absolute")
+                .on_hover_text("Show the absolute value");
+            ui.selectable_value(&mut display.mode, DisplayMode::Normalize, "Normalize")
+                .on_hover_text("Show the smallest value as 0 and the largest as 255");
+            if display.mode == DisplayMode::OffsetScale {
+                ui.add(egui::DragValue::new(&mut display.offset).speed(1.0).prefix("Offset: "));
+                ui.add(egui::DragValue::new(&mut display.scale).speed(0.01).prefix("Scale: "));
+            }
+            if color {
+                egui::ComboBox::from_id_source(("channel_view", target))
+                    .selected_text(match display.view {
+                        ChannelView::All => "RGB",
+                        ChannelView::Channel(c) => CHANNEL_NAMES[c.min(2)],
+                    })
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut display.view, ChannelView::All, "RGB");
+                        for (c, name) in CHANNEL_NAMES.iter().enumerate() {
+                            ui.selectable_value(&mut display.view, ChannelView::Channel(c), *name);
+                        }
+                    });
+            }
+            ui.label(format!("Range: {:.1} to {:.1}", min, max));
+            ui.checkbox(&mut view.show_values, "Values").on_hover_text("Write the value into each cell");
+            quantize = ui.button("Quantize").on_hover_text("Replace the values with the shown gray levels").clicked();
+        });
+        if quantize {
+            self.edit(|s| if target { s.dst_grid.quantize() } else { s.src_grid.quantize() });
+        }
+    }
+
+    /// switch between gray and rgb grids, and convert the source between color spaces
+    fn color_section(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let mut color = self.src_grid.is_color();
+            ui.selectable_value(&mut color, false, "Gray")
+                .on_hover_text("Turn both images into their luma");
+            ui.selectable_value(&mut color, true, "RGB")
+                .on_hover_text("Give both images three channels, which the tools process independently");
+            if color != self.src_grid.is_color() {
+                let weights = self.tool_vars.luma;
+                self.edit(|s| {
+                    s.src_grid.set_color(color, weights);
+                    s.dst_grid.set_color(color, weights);
+                });
+            }
+            let luma = &mut self.tool_vars.luma;
+            let before = *luma;
+            egui::ComboBox::from_label("Luma weights")
+                .selected_text(luma.name())
+                .show_ui(ui, |ui| {
+                    for weights in LumaWeights::ALL {
+                        ui.selectable_value(luma, weights, weights.name());
+                    }
+                });
+            if self.tool_vars.luma != before {
+                self.tool_vars.touch();
+            }
+            ui.add_enabled(self.src_grid.is_color(), egui::Checkbox::new(&mut self.split_channels, "Split channels"));
+        });
+        ui.horizontal(|ui| {
+            let conversion = &mut self.tool_vars.conversion;
+            egui::ComboBox::from_id_source("conversion")
+                .selected_text(conversion.name())
+                .show_ui(ui, |ui| {
+                    for c in Conversion::ALL {
+                        ui.selectable_value(conversion, c, c.name());
+                    }
+                });
+            if ui.add_enabled(self.src_grid.is_color(), egui::Button::new("Convert source into target")).clicked() {
+                let (conversion, weights) = (self.tool_vars.conversion, self.tool_vars.luma);
+                self.edit(|s| {
+                    s.dst_grid.copy_pixels_from(&s.src_grid);
+                    s.dst_grid.map_pixels(|px| conversion.apply(px, weights));
+                });
+            }
+        });
+    }
+
+    /// set the target to the source mapped through the lookup table
+    fn apply_lut(&mut self, lut: [u8; 256]) {
+        self.edit(|s| {
+            let map = s.src_grid.display_map();
+            s.dst_grid.copy_pixels_from(&s.src_grid);
+            s.dst_grid.map_values(|v| lut[map.apply(v) as usize] as f32);
+        });
+    }
+
+    /// the steps of the pipeline, which run on the source one after the other
+    fn pipeline_section(&mut self, ui: &mut egui::Ui) {
+        let count = self.pipeline.steps().len();
+        for i in 0..count {
+            let step = &self.pipeline.steps()[i];
+            let mut enabled = step.enabled;
+            let label = format!("{}. {}", i + 1, step.tool.name());
+            let editing = self.pipeline.editing() == Some(i);
+            ui.horizontal(|ui| {
+                if ui.checkbox(&mut enabled, label).changed() {
+                    self.pipeline.set_enabled(i, enabled);
+                }
+                if ui.selectable_label(editing, "Edit").on_hover_text("Show the parameters of this step in the tool panel").clicked() {
+                    if editing {
+                        self.pipeline.stop_edit(&mut self.tool, &mut self.tool_vars);
+                    } else {
+                        self.pipeline.start_edit(i, &mut self.tool, &mut self.tool_vars);
+                    }
+                }
+                if ui.add_enabled(i > 0, egui::Button::new("⏶")).on_hover_text("Move up").clicked() {
+                    self.pipeline.move_down(i - 1);
+                }
+                if ui.add_enabled(i + 1 < count, egui::Button::new("⏷")).on_hover_text("Move down").clicked() {
+                    self.pipeline.move_down(i);
+                }
+                if ui.button("✖").on_hover_text("Remove").clicked() {
+                    if editing {
+                        self.pipeline.stop_edit(&mut self.tool, &mut self.tool_vars);
+                    }
+                    self.pipeline.remove(i);
+                }
+            });
+        }
+        if let Some(i) = self.pipeline.editing() {
+            ui.label(format!("The tool panel edits step {}.", i + 1));
+        }
+        ui.horizontal(|ui| {
+            // the pen paints instead of processing the image
+            let can_add = self.tool != Tool::Pen && self.pipeline.editing().is_none();
+            if ui.add_enabled(can_add, egui::Button::new("Add current tool"))
+                .on_hover_text("Append the selected tool with its current parameters").clicked() {
+                self.pipeline.push(self.tool, &self.tool_vars);
+            }
+            if ui.add_enabled(self.pipeline.result().is_some(), egui::Button::new("Result to target"))
+                .on_hover_text("Copy the result of the last step into the target image").clicked() {
+                if let Some(result) = self.pipeline.result() {
+                    let result = result.clone();
+                    self.edit(|s| {
+                        s.dst_grid.copy_pixels_from(&result);
+                    });
+                }
+            }
+        });
+    }
+
+    fn histogram_section(&mut self, ui: &mut egui::Ui) {
+        // only recomputed when a grid changes, but always up to date
+        let src_hist = self.src_histogram.get(&self.src_grid).clone();
+        let dst_hist = self.dst_histogram.get(&self.dst_grid).clone();
+        ui.horizontal(|ui| {
+            ui.vertical(|ui| {
+                ui.label("Source");
+                src_hist.draw(ui, egui::vec2(256.0, 80.0));
+            });
+            ui.vertical(|ui| {
+                ui.label("Target");
+                dst_hist.draw(ui, egui::vec2(256.0, 80.0));
+            });
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Equalize").on_hover_text("Equalize the source histogram into the target").clicked() {
+                self.apply_lut(src_hist.equalize_lut());
+            }
+            if ui.button("Match to target").on_hover_text("Give the source the target's histogram").clicked() {
+                self.apply_lut(src_hist.match_lut(&dst_hist));
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Stretch contrast").on_hover_text("Map the percentiles to 0 and 255").clicked() {
+                self.apply_lut(src_hist.stretch_lut(self.histogram.stretch_low, self.histogram.stretch_high));
+            }
+            let vars = &mut self.histogram;
+            ui.add(egui::Slider::new(&mut vars.stretch_low, 0.0..=50.0).text("Low").suffix("%"));
+            ui.add(egui::Slider::new(&mut vars.stretch_high, 50.0..=100.0).text("High").suffix("%"));
+        });
+    }
+
+    /// resize both grids. the target always has the same size as the source.
+    fn resize_grids(&mut self, width: u32, height: u32) {
+        self.history.begin_edit(&self.src_grid, &self.dst_grid);
+        let (src_base, dst_base) = self.resize_base
+            .get_or_insert_with(|| (self.src_grid.clone(), self.dst_grid.clone()));
+        self.resize.resize(src_base, &mut self.src_grid, width, height);
+        self.resize.resize(dst_base, &mut self.dst_grid, width, height);
+    }
+
+    fn size_row(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let mut width = self.src_grid.width();
+            let mut height = self.src_grid.height();
+            let w_response = ui.add(egui::DragValue::new(&mut width).clamp_range(1..=MAX_SIZE).prefix("Width: "));
+            let h_response = ui.add(egui::DragValue::new(&mut height).clamp_range(1..=MAX_SIZE).prefix("Height: "));
+            if w_response.changed() || h_response.changed() {
+                self.resize_grids(width, height);
+            }
+            let editing = [w_response, h_response].iter().any(|r| r.dragged() || r.has_focus());
+            if !editing {
+                self.resize_base = None;
+            }
+        });
+        ui.horizontal(|ui| {
+            let resize = &mut self.resize;
+            ui.selectable_value(&mut resize.mode, ResizeMode::Crop, "Crop")
+                .on_hover_text("Cut off the content or repeat the edge pixels");
+            ui.selectable_value(&mut resize.mode, ResizeMode::Pad, "Pad")
+                .on_hover_text("Cut off the content or fill new pixels with a color");
+            ui.selectable_value(&mut resize.mode, ResizeMode::Resample, "Resample")
+                .on_hover_text("Scale the content to the new size");
+            match resize.mode {
+                ResizeMode::Crop => {},
+                ResizeMode::Pad => {
+                    ui.add(egui::Slider::new(&mut resize.pad_color, 0..=255).text("Color"));
+                },
+                ResizeMode::Resample => resampling_selector(ui, &mut resize.resampling),
+            }
+        });
+    }
+
+    /// cell size, margin and zoom, which all the grids share
+    fn view_row(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let view = &mut self.src_view;
+            let mut changed = ui.add(egui::Slider::new(&mut view.pix_size, 2..=48).text("Cell size")).changed();
+            changed |= ui.add(egui::Slider::new(&mut view.margin, 0..=8).text("Margin")).changed();
+            ui.label(format!("Zoom: {:.0}%", view.zoom() * 100.0));
+            if ui.button("100%").clicked() {
+                view.set_zoom(1.0);
+                changed = true;
+            }
+            if ui.button("Fit").on_hover_text("Zoom so the whole image fits into the window").clicked() {
+                self.src_view.fit_to_view();
+                self.dst_view.fit_to_view();
+            }
+            if changed {
+                self.dst_view.copy_view_from(&self.src_view);
+            }
+        });
+        ui.label("Scroll over a grid to zoom, drag with the middle button or with Shift to pan.");
+    }
+
+    fn import_row(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("Load image…").on_hover_text("You can also drop an image file onto the window").clicked() {
+                self.loader.open_dialog(ui.ctx());
+            }
+            let import = &mut self.import;
+            ui.add(egui::DragValue::new(&mut import.width).clamp_range(1..=MAX_SIZE).prefix("Width: "));
+            ui.add_enabled(
+                !import.keep_aspect,
+                egui::DragValue::new(&mut import.height).clamp_range(1..=MAX_SIZE).prefix("Height: "));
+            ui.checkbox(&mut import.keep_aspect, "Keep aspect ratio");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Resampling:");
+            resampling_selector(ui, &mut self.import.resampling);
+        });
+        if let Some(e) = &self.import_error {
+            ui.colored_label(ui.visuals().error_fg_color, e);
+        }
+    }
+
+    fn export_row(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.export.format, ExportFormat::Png, "PNG");
+            ui.selectable_value(&mut self.export.format, ExportFormat::Pgm, "PGM");
+            ui.selectable_value(&mut self.export.format, ExportFormat::Csv, "CSV");
+            ui.add_enabled(
+                self.export.format == ExportFormat::Png,
+                egui::DragValue::new(&mut self.export.scale).clamp_range(1..=export::MAX_SCALE).prefix("Scale: ").suffix("×"));
+        });
+        ui.horizontal(|ui| {
+            let mut result = None;
+            if ui.button("Save source").clicked() {
+                result = Some(self.export_grid(&self.src_grid, "source"));
+            }
+            if ui.button("Save target").clicked() {
+                result = Some(self.export_grid(&self.dst_grid, "target"));
+            }
+            if let Some(result) = result {
+                self.export_error = result.err();
+            }
+        });
+        if let Some(e) = &self.export_error {
+            ui.colored_label(ui.visuals().error_fg_color, e);
+        }
+    }
+
+    fn export_grid(&self, grid: &PixGrid, name: &str) -> Result<(), String> {
+        let bytes = export::encode(grid, &self.export);
+        let file_name = format!("{}.{}", name, self.export.format.extension());
+        export::save(&bytes, &file_name, self.export.format)
+    }
 }
 
 impl eframe::App for ImgProcDemo {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, eframe::APP_KEY, self);
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let (undo, redo) = ctx.input_mut(|i| (
+            i.consume_key(egui::Modifiers::COMMAND, egui::Key::Z),
+            i.consume_key(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::Z),
+        ));
+        if undo {
+            self.history.undo(&mut self.src_grid, &mut self.dst_grid);
+        }
+        if redo {
+            self.history.redo(&mut self.src_grid, &mut self.dst_grid);
+        }
+        if let Some(file) = self.loader.poll(ctx) {
+            self.import_image(file);
+        }
+        // the filter mask follows the grid size
+        self.tool_vars.ensure_filter_size(self.src_grid.width(), self.src_grid.height());
+        if self.tool == Tool::Canny {
+            self.canny_cache.update(&self.src_grid, &self.tool_vars.canny, &self.tool_vars.border, self.tool_vars.luma);
+        }
+        if self.tool == Tool::Threshold && self.tool_vars.threshold.mode == ThresholdMode::Otsu {
+            self.otsu_cache.update(&self.src_grid);
+        }
+        self.pipeline.sync(self.tool, &self.tool_vars);
+        self.pipeline.update(&self.src_grid);
+
         egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading(egui::RichText::new("Image Processing Demo").strong().size(24.0));
+            ui.horizontal(|ui| {
+                ui.heading(egui::RichText::new("Image Processing Demo").strong().size(24.0));
+                ui.selectable_value(&mut self.graph_mode, false, "Grids");
+                ui.selectable_value(&mut self.graph_mode, true, "Node graph");
+            });
+            if self.graph_mode {
+                if let Some(result) = self.graph_editor.show(ui, &mut self.graph, &self.src_grid) {
+                    if result.same_shape(&self.src_grid) {
+                        self.edit(|s| s.dst_grid.copy_pixels_from(&result));
+                    }
+                }
+                return;
+            }
             ui.horizontal(|ui| {
                 // grid column
                 ui.vertical(|ui| {
                     ui.label(egui::RichText::new("Source Image:").size(16.0));
-                    self.src_grid.draw(ui);
+                    self.display_row(ui, false);
+                    self.src_view.draw(ui, &self.src_grid);
+                    // both grids show the same part of the image
+                    if self.src_view.view_changed() {
+                        self.dst_view.copy_view_from(&self.src_view);
+                    }
+                    if self.split_channels && self.src_grid.is_color() {
+                        ui.horizontal(|ui| {
+                            let channels = self.src_grid.channel_grids();
+                            for ((channel, view), name) in channels.iter().zip(&mut self.channel_views).zip(CHANNEL_NAMES) {
+                                ui.vertical(|ui| {
+                                    ui.label(name);
+                                    view.pix_size = (self.src_view.pix_size / 3).max(2);
+                                    view.draw(ui, channel);
+                                });
+                            }
+                        });
+                    }
                     ui.label(""); // little spacer
                     ui.label(egui::RichText::new("Target Image:").size(16.0));
-                    self.dst_grid.draw(ui);
-                    self.tool.interact(ui, &mut self.tool_vars, &mut self.src_grid, &mut self.dst_grid);
-                });
-
-                // tools column
-                ui.vertical(|ui| {
-                    ui.label(egui::RichText::new("Tool:").size(16.0));
-                    self.pen_row(ui);
-                    ui.selectable_value(&mut self.tool, Tool::Cpy, "Copy");
-                    self.conv_row(ui);
-                    self.bool_row(ui);
-
-                    ui.label(egui::RichText::new("Actions:").size(16.0));
-                    if ui.button("Reset").clicked() {
-                        self.src_grid.reset_to_color(180);
-                        self.dst_grid.reset_to_color(180);
+                    self.display_row(ui, true);
+                    self.dst_view.draw(ui, &self.dst_grid);
+                    if self.dst_view.view_changed() {
+                        self.src_view.copy_view_from(&self.dst_view);
                     }
-                    if ui.button("Apply tool to whole image").clicked() {
-                        self.tool.apply_to_whole_image(&self.tool_vars, &mut self.src_grid, &mut self.dst_grid);
+                    // a stroke lasts from pressing on the source grid until the button is released,
+                    // so dragging the pen is undone as a whole
+                    if self.src_view.pressed() {
+                        self.history.begin_edit(&self.src_grid, &self.dst_grid);
                     }
-                    if ui.button("Copy target to source").clicked() {
-                        self.src_grid.copy_pixels_from(&mut self.dst_grid);
+                    self.tool.interact(ui, &self.tool_vars, &self.canny_cache, self.otsu_cache.thresholds(), &mut self.src_grid, &self.src_view, &mut self.dst_grid, &self.dst_view);
+                    if !self.pipeline.is_empty() {
+                        ui.label(""); // little spacer
+                        ui.label(egui::RichText::new("Pipeline results:").size(16.0));
+                        self.pipeline.draw_results(ui, &self.src_view);
                     }
+                    ui.label(""); // little spacer
+                    ui.label(egui::RichText::new("Spectrum:").size(16.0));
+                    self.spectrum_section(ui);
+                });
+
+                // the lookup table of the point operation, so input -> output pairs can be read off
+                if self.tool == Tool::Lut {
+                    ui.vertical(|ui| {
+                        ui.label(egui::RichText::new("Lookup table:").size(16.0));
+                        let hovered = self.src_view.hovered_idx().map(|(ix, iy)| self.src_grid.get_displayed(ix, iy));
+                        self.tool_vars.point_op.table(ui, hovered);
+                    });
+                }
+
+                // tools column, scrollable since it doesn't fit into small windows
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    ui.vertical(|ui| {
+                        ui.label(egui::RichText::new("Tool:").size(16.0));
+                        self.pen_row(ui);
+                        ui.selectable_value(&mut self.tool, Tool::Cpy, "Copy");
+                        self.conv_row(ui);
+                        self.bool_row(ui);
+                        self.rank_row(ui);
+                        self.morph_row(ui);
+                        self.threshold_row(ui);
+                        self.lut_row(ui);
+                        self.gradient_row(ui);
+                        self.canny_row(ui);
+                        self.frequency_row(ui);
+                        self.border_row(ui);
+
+                        ui.label(egui::RichText::new("Color:").size(16.0));
+                        self.color_section(ui);
+
+                        ui.label(egui::RichText::new("Actions:").size(16.0));
+                        ui.horizontal(|ui| {
+                            if ui.add_enabled(self.history.can_undo(), egui::Button::new("Undo"))
+                                .on_hover_text("Ctrl+Z").clicked() {
+                                self.history.undo(&mut self.src_grid, &mut self.dst_grid);
+                            }
+                            if ui.add_enabled(self.history.can_redo(), egui::Button::new("Redo"))
+                                .on_hover_text("Ctrl+Shift+Z").clicked() {
+                                self.history.redo(&mut self.src_grid, &mut self.dst_grid);
+                            }
+                        });
+                        if ui.button("Reset").clicked() {
+                            self.edit(|s| {
+                                s.src_grid.reset_to_color(180);
+                                s.dst_grid.reset_to_color(180);
+                            });
+                        }
+                        if ui.button("Apply tool to whole image").clicked() {
+                            self.edit(|s| s.tool.apply_to_whole_image(&s.tool_vars, &mut s.src_grid, &mut s.dst_grid));
+                        }
+                        if ui.button("Copy target to source").clicked() {
+                            self.edit(|s| s.src_grid.copy_pixels_from(&s.dst_grid));
+                        }
+
+                        ui.label(egui::RichText::new("Pipeline:").size(16.0));
+                        self.pipeline_section(ui);
+
+                        ui.label(egui::RichText::new("Histograms:").size(16.0));
+                        self.histogram_section(ui);
+
+                        ui.label(egui::RichText::new("Grid size:").size(16.0));
+                        self.size_row(ui);
+
+                        ui.label(egui::RichText::new("View:").size(16.0));
+                        self.view_row(ui);
+
+                        ui.label(egui::RichText::new("Import:").size(16.0));
+                        self.import_row(ui);
+
+                        ui.label(egui::RichText::new("Export:").size(16.0));
+                        self.export_row(ui);
+                    });
                 });
             });
         });
+
+        if !ctx.input(|i| i.pointer.any_down()) {
+            self.history.end_edit(&self.src_grid, &self.dst_grid);
+        }
+
+        // hint that a file being dragged over the window can be dropped
+        if ctx.input(|i| !i.raw.hovered_files.is_empty()) {
+            let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("file_drop")));
+            let screen = ctx.screen_rect();
+            painter.rect_filled(screen, Rounding::ZERO, Color32::from_black_alpha(180));
+            painter.text(
+                screen.center(),
+                egui::Align2::CENTER_CENTER,
+                "Drop to import into the source image",
+                egui::FontId::proportional(24.0),
+                Color32::WHITE);
+        }
     }
 }
 
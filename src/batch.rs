@@ -0,0 +1,294 @@
+use crate::export::{self, ExportFormat, ExportSettings, MAX_SCALE};
+use crate::image::{Border, BorderMode, ImageBuf};
+use crate::import::{self, Resampling};
+use crate::kernel::{Convolution, PresetParams, PRESETS};
+use crate::morphology;
+use crate::pix_grid::PixGrid;
+
+const USAGE: &str = "\
+usage: img_proc_batch [OPTIONS] INPUT OUTPUT
+
+reads a png, jpeg or pgm/ppm image, runs the operations in the given order
+and writes the result as png, pgm or csv, depending on the extension of OUTPUT.
+
+operations:
+  --conv PRESET          convolution with a preset kernel, see the list below
+  --kernel FILE          convolution with a kernel from a text file, one row per line.
+                         weights may be fractions like 1/16, a line `zero-centered`
+                         shifts the result by 127
+  --dilate SHAPE         boolean dilation with a 3x3 shape: cross, square
+                         or nine 0/1 digits row by row, like 010111010
+  --erode SHAPE          boolean erosion, see --dilate
+  --copy                 copy the image unchanged
+
+settings for the operations that follow them:
+  --border MODE          clamp, mirror, wrap, skip or constant:VALUE (default clamp)
+  --threshold VALUE      values above it are true for --dilate and --erode, 0 to 254 (default 127)
+  --radius N, --sigma S, --amount A, --angle DEGREES
+                         parameters of the presets that use them, limited to radius 0 to 3,
+                         sigma 0.2 to 4, amount 0 to 4 and angle 0 to 180
+
+input and output:
+  --rgb                  process the color channels independently instead of the gray image
+  --width N              resample the image to N pixels wide, keeping the aspect ratio
+  --resampling MODE      nearest, box or bilinear (default box)
+  --scale N              write each pixel of a png as an NxN block, 1 to 32
+  -h, --help             show this help
+";
+
+/// the help of the batch binary, with the names of the presets
+pub fn usage() -> String {
+    let ids: Vec<&str> = PRESETS.iter().map(|p| p.id).collect();
+    format!("{}\npresets for --conv:\n  {}\n", USAGE, ids.join(", "))
+}
+
+/// an operation that runs on the whole image, channel by channel.
+/// these are the same operations the tools of the app apply to the whole image.
+pub enum Op {
+    Conv(Convolution),
+    /// binary dilation or erosion with a 3x3 mask, see `morphology::boolean_at`
+    Boolean { mask: [[bool; 3]; 3], dilation: bool, threshold: u8 },
+    Copy,
+}
+
+impl Op {
+    /// the result for a single channel. pixels that are skipped because of the border mode keep their value.
+    pub fn apply(&self, src: &ImageBuf<f32>, border: &Border) -> ImageBuf<f32> {
+        let mut dst = src.clone();
+        for (x, y, _) in src.enumerate() {
+            let (ix, iy) = (x as i32, y as i32);
+            let value = match self {
+                Op::Conv(conv) => conv.apply_at(src, ix, iy, border),
+                Op::Boolean { mask, dilation, threshold } => {
+                    morphology::boolean_at(src, ix, iy, mask, *dilation, *threshold as f32, border)
+                },
+                Op::Copy => Some(src.get(x, y)),
+            };
+            if let Some(value) = value {
+                dst.set(x, y, value);
+            }
+        }
+        dst
+    }
+}
+
+/// an operation with the border handling it uses
+pub struct Step {
+    pub op: Op,
+    pub border: Border,
+}
+
+/// everything a run of the batch binary does, parsed from its arguments
+pub struct Job {
+    pub input: String,
+    pub output: String,
+    pub color: bool,
+    /// resample the image to this width, None keeps its size
+    pub width: Option<u32>,
+    pub resampling: Resampling,
+    pub export: ExportSettings,
+    pub steps: Vec<Step>,
+}
+
+/// the 3x3 mask of a boolean operation
+fn parse_shape(s: &str) -> Result<[[bool; 3]; 3], String> {
+    match s {
+        "cross" => Ok([[false, true, false], [true, true, true], [false, true, false]]),
+        "square" => Ok([[true; 3]; 3]),
+        _ if s.len() == 9 && s.chars().all(|c| c == '0' || c == '1') => {
+            let bits: Vec<bool> = s.chars().map(|c| c == '1').collect();
+            Ok(std::array::from_fn(|y| std::array::from_fn(|x| bits[3 * y + x])))
+        },
+        _ => Err(format!("unknown shape: {}", s)),
+    }
+}
+
+fn parse_border(s: &str) -> Result<Border, String> {
+    let (mode, constant) = match s.split_once(':') {
+        Some(("constant", value)) => (BorderMode::Constant, parse_number(value)?),
+        _ => (match s {
+            "clamp" => BorderMode::Clamp,
+            "mirror" => BorderMode::Mirror,
+            "wrap" => BorderMode::Wrap,
+            "skip" => BorderMode::Skip,
+            _ => return Err(format!("unknown border mode: {}", s)),
+        }, 0),
+    };
+    Ok(Border { mode, constant })
+}
+
+fn parse_number<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("invalid number: {}", s))
+}
+
+impl Job {
+    /// parse the arguments, without the program name. kernel files are read right away.
+    /// returns Ok(None) if only the help was asked for.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut args = args.into_iter();
+        let mut files = Vec::new();
+        let mut job = Job {
+            input: String::new(),
+            output: String::new(),
+            color: false,
+            width: None,
+            resampling: Resampling::Box,
+            export: ExportSettings::default(),
+            steps: Vec::new(),
+        };
+        let mut border = Border::default();
+        let mut threshold = 127;
+        let mut params = PresetParams::default();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with('-') {
+                files.push(arg);
+                continue;
+            }
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            let op = match arg.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "--conv" => {
+                    let id = value()?;
+                    let preset = PRESETS.iter().find(|p| p.id == id).ok_or(format!("unknown preset: {}", id))?;
+                    // the presets can't handle every value, a sigma of 0 would make them NaN
+                    params.clamp();
+                    Some(Op::Conv((preset.build)(&params)))
+                },
+                "--kernel" => {
+                    let path = value()?;
+                    let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+                    Some(Op::Conv(Convolution::parse(&text).map_err(|e| format!("{}: {}", path, e))?))
+                },
+                "--dilate" | "--erode" => Some(Op::Boolean {
+                    mask: parse_shape(&value()?)?,
+                    dilation: arg == "--dilate",
+                    threshold,
+                }),
+                "--copy" => Some(Op::Copy),
+                "--border" => { border = parse_border(&value()?)?; None },
+                "--threshold" => { threshold = parse_number::<u8>(&value()?)?.min(morphology::MAX_THRESHOLD); None },
+                "--radius" => { params.radius = parse_number(&value()?)?; None },
+                "--sigma" => { params.sigma = parse_number(&value()?)?; None },
+                "--amount" => { params.amount = parse_number(&value()?)?; None },
+                "--angle" => { params.angle = parse_number(&value()?)?; None },
+                "--rgb" => { job.color = true; None },
+                "--width" => { job.width = Some(parse_number::<u32>(&value()?)?.max(1)); None },
+                "--resampling" => {
+                    job.resampling = match value()?.as_str() {
+                        "nearest" => Resampling::Nearest,
+                        "box" => Resampling::Box,
+                        "bilinear" => Resampling::Bilinear,
+                        other => return Err(format!("unknown resampling: {}", other)),
+                    };
+                    None
+                },
+                "--scale" => { job.export.scale = parse_number::<u32>(&value()?)?.clamp(1, MAX_SCALE); None },
+                _ => return Err(format!("unknown option: {}", arg)),
+            };
+            if let Some(op) = op {
+                job.steps.push(Step { op, border });
+            }
+        }
+        let [input, output]: [String; 2] = files.try_into().map_err(|_| "expected an input and an output file".to_string())?;
+        let extension = output.rsplit_once('.').map(|(_, ext)| ext.to_lowercase()).unwrap_or_default();
+        job.export.format = [ExportFormat::Png, ExportFormat::Pgm, ExportFormat::Csv].into_iter()
+            .find(|format| format.extension() == extension)
+            .ok_or(format!("can't write {}, the output must be a png, pgm or csv file", output))?;
+        (job.input, job.output) = (input, output);
+        Ok(Some(job))
+    }
+
+    /// run the steps on an image file and return the encoded result
+    pub fn process(&self, bytes: &[u8]) -> Result<Vec<u8>, String> {
+        let img = import::decode(bytes, self.color)?;
+        let (width, height) = match self.width {
+            Some(width) => (width, ((width as f32 * img.height() as f32 / img.width() as f32).round() as u32).max(1)),
+            None => (img.width(), img.height()),
+        };
+        let planes = import::planes(&img, width, height, self.resampling).into_iter()
+            .map(|plane| self.steps.iter().fold(plane, |plane, step| step.op.apply(&plane, &step.border)))
+            .collect();
+        Ok(export::encode(&PixGrid::from_planes(planes), &self.export))
+    }
+
+    /// read the input, process it and write the output
+    pub fn run(&self) -> Result<(), String> {
+        let bytes = std::fs::read(&self.input).map_err(|e| format!("{}: {}", self.input, e))?;
+        let result = self.process(&bytes)?;
+        std::fs::write(&self.output, result).map_err(|e| format!("{}: {}", self.output, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Option<Job>, String> {
+        Job::parse(args.split_whitespace().map(str::to_string))
+    }
+
+    fn job(args: &str) -> Job {
+        parse(args).unwrap().unwrap()
+    }
+
+    #[test]
+    fn valid_command_line() {
+        let job = job("--rgb --width 40 --border wrap --conv gaussian in.png --threshold 100 --dilate cross --copy out.csv");
+        assert_eq!((job.input.as_str(), job.output.as_str()), ("in.png", "out.csv"));
+        assert!(job.color);
+        assert_eq!(job.width, Some(40));
+        assert!(job.export.format == ExportFormat::Csv);
+        assert_eq!(job.steps.len(), 3);
+        assert!(matches!(job.steps[0].op, Op::Conv(_)));
+        assert!(job.steps[0].border.mode == BorderMode::Wrap);
+        assert!(matches!(job.steps[1].op, Op::Boolean { dilation: true, threshold: 100, .. }));
+        assert!(matches!(job.steps[2].op, Op::Copy));
+    }
+
+    #[test]
+    fn help() {
+        assert!(parse("--help").unwrap().is_none());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("--frobnicate in.png out.png").err().unwrap(), "unknown option: --frobnicate");
+        assert_eq!(parse("in.png out.png --sigma").err().unwrap(), "--sigma needs a value");
+        assert!(parse("in.png").is_err());
+        assert!(parse("in.png out.gif").is_err());
+        assert!(parse("--conv nothing in.png out.png").is_err());
+        assert!(parse("--erode 0101 in.png out.png").is_err());
+        assert!(parse("--border constant:x in.png out.png").is_err());
+        assert!(parse("--threshold 300 in.png out.png").is_err());
+    }
+
+    #[test]
+    fn numbers_are_limited() {
+        let job = job("--threshold 255 --erode square --scale 1000 in.png out.png");
+        assert!(matches!(job.steps[0].op, Op::Boolean { threshold: 254, .. }));
+        assert_eq!(job.export.scale, MAX_SCALE);
+        assert_eq!(self::job("--scale 0 in.png out.png").export.scale, 1);
+    }
+
+    #[test]
+    fn sigma_0_gives_numbers() {
+        let job = job("--sigma 0 --radius 100 --conv gaussian in.png out.png");
+        let Op::Conv(conv) = &job.steps[0].op else { unreachable!() };
+        assert_eq!(conv.right, 3);
+        let img = ImageBuf::new(5, 5, 100.0);
+        assert!(job.steps[0].op.apply(&img, &Border::default()).iter().all(|v| (v - 100.0).abs() < 1e-3));
+    }
+
+    #[test]
+    fn ops() {
+        let img = ImageBuf::from_rows(vec![vec![0.0, 0.0, 0.0], vec![0.0, 200.0, 0.0], vec![0.0, 0.0, 0.0]]).unwrap();
+        let border = Border::default();
+        assert!(Op::Copy.apply(&img, &border) == img);
+        let dilated = Op::Boolean { mask: parse_shape("cross").unwrap(), dilation: true, threshold: 127 }.apply(&img, &border);
+        assert_eq!(dilated.to_rows(), vec![vec![0.0, 255.0, 0.0], vec![255.0, 255.0, 255.0], vec![0.0, 255.0, 0.0]]);
+        // skipped pixels keep their value
+        let skip = Border { mode: BorderMode::Skip, constant: 0 };
+        let eroded = Op::Boolean { mask: [[true; 3]; 3], dilation: false, threshold: 127 }.apply(&img, &skip);
+        assert_eq!(eroded.to_rows(), vec![vec![0.0, 0.0, 0.0], vec![0.0, 0.0, 0.0], vec![0.0, 0.0, 0.0]]);
+    }
+}
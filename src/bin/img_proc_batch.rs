@@ -0,0 +1,20 @@
+//! runs the image operations of the demo without a window, e.g. to produce
+//! worksheet solutions or regression outputs in scripts
+
+use img_proc_demo::batch::{usage, Job};
+
+fn main() {
+    match Job::parse(std::env::args().skip(1)) {
+        Ok(Some(job)) => {
+            if let Err(e) = job.run() {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Ok(None) => print!("{}", usage()),
+        Err(e) => {
+            eprintln!("error: {}\nrun with --help to see the options", e);
+            std::process::exit(2);
+        },
+    }
+}
@@ -0,0 +1,292 @@
+use crate::gradient::{self, DerivativePair};
+use crate::kernel;
+use crate::image::{Border, BorderTap, ImageBuf};
+use crate::color::LumaWeights;
+use crate::pix_grid::{Display, PixGrid};
+
+/// the stages of the canny edge detector, in the order they run
+#[derive(PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum CannyStage {
+    /// gaussian blur against noise
+    Smooth,
+    /// gradient magnitude of the smoothed image
+    Gradient,
+    /// keep only the local maxima along the gradient direction
+    Suppress,
+    /// classify the remaining maxima as strong or weak edges
+    Threshold,
+    /// keep the weak edges that are connected to strong ones
+    Hysteresis,
+}
+
+impl CannyStage {
+    pub const ALL: [CannyStage; 5] = [
+        CannyStage::Smooth, CannyStage::Gradient, CannyStage::Suppress, CannyStage::Threshold, CannyStage::Hysteresis,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CannyStage::Smooth => "Smoothing",
+            CannyStage::Gradient => "Gradient",
+            CannyStage::Suppress => "Non-maximum suppression",
+            CannyStage::Threshold => "Double threshold",
+            CannyStage::Hysteresis => "Hysteresis",
+        }
+    }
+
+    fn index(&self) -> usize {
+        CannyStage::ALL.iter().position(|s| s == self).unwrap_or(0)
+    }
+
+    /// the following stage, or None for the last one
+    pub fn next(&self) -> Option<CannyStage> {
+        CannyStage::ALL.get(self.index() + 1).copied()
+    }
+
+    /// the previous stage, or None for the first one
+    pub fn prev(&self) -> Option<CannyStage> {
+        self.index().checked_sub(1).map(|i| CannyStage::ALL[i])
+    }
+
+    /// how far from a pixel the source values its result depends on can be.
+    /// hysteresis can follow an edge through the whole image, this only covers its own pixel.
+    pub fn reach(&self, radius: i32) -> i32 {
+        match self {
+            CannyStage::Smooth => radius,
+            CannyStage::Gradient => radius + 1,
+            CannyStage::Suppress | CannyStage::Threshold | CannyStage::Hysteresis => radius + 2,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum EdgeClass {
+    NonEdge,
+    /// between the thresholds, only an edge if it is connected to a strong one
+    Weak,
+    /// above the high threshold
+    Strong,
+}
+
+impl EdgeClass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeClass::NonEdge => "none",
+            EdgeClass::Weak => "weak",
+            EdgeClass::Strong => "strong",
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Canny {
+    /// radius of the gaussian kernel
+    pub radius: i32,
+    pub sigma: f32,
+    pub pair: DerivativePair,
+    /// gradient magnitudes for weak and strong edges
+    pub low: f32,
+    pub high: f32,
+    /// the stage that is shown and applied
+    pub stage: CannyStage,
+}
+
+impl Default for Canny {
+    fn default() -> Self {
+        Self { radius: 2, sigma: 1.0, pair: DerivativePair::Sobel, low: 20.0, high: 50.0, stage: CannyStage::Hysteresis }
+    }
+}
+
+/// the results of all the stages. None marks pixels that are skipped because of the border mode.
+pub struct CannyImages {
+    pub smoothed: ImageBuf<Option<f32>>,
+    /// normalized partial derivatives of the smoothed image
+    pub gradient: ImageBuf<Option<(f32, f32)>>,
+    /// the gradient magnitude at local maxima, 0 elsewhere
+    pub suppressed: ImageBuf<Option<f32>>,
+    pub classes: ImageBuf<Option<EdgeClass>>,
+    pub edges: ImageBuf<Option<bool>>,
+}
+
+/// the stages for every channel of a grid and for its luma, so hovering doesn't run the
+/// whole detector each frame. they are only recomputed when the grid or the parameters change.
+#[derive(Default)]
+pub struct CannyCache {
+    /// generation and display settings of the grid, and the parameters without the stage
+    key: Option<(u64, Display, Canny, Border, LumaWeights)>,
+    channels: Vec<CannyImages>,
+    /// None for gray grids, whose luma is their only channel
+    luma: Option<CannyImages>,
+}
+
+impl CannyCache {
+    pub fn update(&mut self, grid: &PixGrid, canny: &Canny, border: &Border, weights: LumaWeights) {
+        // the stage only selects which of the images is shown
+        let params = Canny { stage: CannyStage::Hysteresis, ..canny.clone() };
+        let key = Some((grid.generation(), grid.display, params, *border, weights));
+        if self.key == key {
+            return;
+        }
+        self.channels = grid.planes().iter().map(|plane| CannyImages::new(canny, plane, border)).collect();
+        self.luma = grid.is_color().then(|| CannyImages::new(canny, grid.to_gray(weights).plane(0), border));
+        self.key = key;
+    }
+
+    /// the shown stage at x, y for every channel, see `CannyImages::value`
+    pub fn values_at(&self, stage: CannyStage, x: u32, y: u32) -> Option<Vec<f32>> {
+        if self.channels.is_empty() {
+            return None;
+        }
+        self.channels.iter().map(|images| images.value(stage, x, y)).collect()
+    }
+
+    pub fn luma(&self) -> Option<&CannyImages> {
+        self.luma.as_ref().or(self.channels.first())
+    }
+}
+
+/// f(x, y) for every pixel of an image of the size of `src`
+fn stage<T: Copy>(src: &ImageBuf<f32>, f: impl Fn(i32, i32) -> Option<T>) -> ImageBuf<Option<T>> {
+    ImageBuf::from_fn(src.width(), src.height(), |x, y| f(x as i32, y as i32))
+}
+
+/// read an intermediate image with the same border handling as the source image.
+/// `outside` is what the stage gives for an image of the border constant.
+fn read<T: Copy>(src: &ImageBuf<f32>, border: &Border, img: &ImageBuf<Option<T>>, x: i32, y: i32, outside: T) -> Option<T> {
+    match src.resolve_border(x, y, border) {
+        BorderTap::Pixel(x, y) => img.get(x, y),
+        BorderTap::Constant(_) => Some(outside),
+        BorderTap::Skip => None,
+    }
+}
+
+/// step to the neighbour in the gradient direction, quantized to multiples of 45°.
+/// the angle is counterclockwise, but the grid's y axis points down.
+fn direction_step(gx: f32, gy: f32) -> (i32, i32) {
+    let sector = (gradient::angle(gx, gy) / 45.0).round() as i32 % 4;
+    match sector {
+        0 => (1, 0),
+        1 => (1, -1),
+        2 => (0, -1),
+        _ => (-1, -1),
+    }
+}
+
+impl CannyImages {
+    /// run all the stages. smoothing and gradient are convolutions, the gradient works on the smoothed image.
+    pub fn new(canny: &Canny, src: &ImageBuf<f32>, border: &Border) -> Self {
+        let blur = kernel::gaussian_blur(canny.radius, canny.sigma);
+        let smoothed = stage(src, |x, y| blur.apply_at(src, x, y, border));
+        // skipped pixels of the smoothed image keep their source value, they must not be read
+        let smoothed_img = ImageBuf::from_fn(src.width(), src.height(), |x, y| smoothed.get(x, y).unwrap_or(src.get(x, y)));
+        let gradient = stage(src, |x, y| {
+            let taps_smoothed = (-1..=1).all(|dy| (-1..=1).all(|dx| {
+                read(src, border, &smoothed, x + dx, y + dy, 0.0).is_some()
+            }));
+            if !taps_smoothed {
+                return None;
+            }
+            canny.pair.sums(&smoothed_img, x, y, border)
+        });
+        Self::from_gradient(canny, src, border, smoothed, gradient)
+    }
+
+    /// run the stages after the gradient
+    fn from_gradient(canny: &Canny, src: &ImageBuf<f32>, border: &Border, smoothed: ImageBuf<Option<f32>>, gradient: ImageBuf<Option<(f32, f32)>>) -> Self {
+        let magnitude = |x, y| read(src, border, &gradient, x, y, (0.0, 0.0)).map(|(gx, gy)| gx.hypot(gy));
+        let suppressed = stage(src, |x, y| {
+            let (gx, gy) = gradient.get(x as u32, y as u32)?;
+            let (dx, dy) = direction_step(gx, gy);
+            let m = gx.hypot(gy);
+            let ahead = magnitude(x + dx, y + dy)?;
+            let behind = magnitude(x - dx, y - dy)?;
+            // on a plateau of two equal values only one of them survives, so the edge stays thin
+            Some(if m > ahead && m >= behind { m } else { 0.0 })
+        });
+        let classes = stage(src, |x, y| {
+            let m = suppressed.get(x as u32, y as u32)?;
+            Some(if m >= canny.high {
+                EdgeClass::Strong
+            } else if m >= canny.low {
+                EdgeClass::Weak
+            } else {
+                EdgeClass::NonEdge
+            })
+        });
+        let edges = hysteresis(&classes);
+        Self { smoothed, gradient, suppressed, classes, edges }
+    }
+
+    /// the value the given stage shows at x, y
+    pub fn value(&self, stage: CannyStage, x: u32, y: u32) -> Option<f32> {
+        match stage {
+            CannyStage::Smooth => self.smoothed.get(x, y),
+            CannyStage::Gradient => self.gradient.get(x, y).map(|(gx, gy)| gx.hypot(gy)),
+            CannyStage::Suppress => self.suppressed.get(x, y),
+            CannyStage::Threshold => self.classes.get(x, y).map(|c| match c {
+                EdgeClass::NonEdge => 0.0,
+                EdgeClass::Weak => 127.0,
+                EdgeClass::Strong => 255.0,
+            }),
+            CannyStage::Hysteresis => self.edges.get(x, y).map(|e| if e { 255.0 } else { 0.0 }),
+        }
+    }
+}
+
+/// follow the weak edges that are 8-connected to strong ones
+fn hysteresis(classes: &ImageBuf<Option<EdgeClass>>) -> ImageBuf<Option<bool>> {
+    let mut edges = classes.map(|c| c.map(|c| c == EdgeClass::Strong));
+    let mut stack: Vec<(u32, u32)> = edges.enumerate().filter(|&(_, _, e)| e == Some(true)).map(|(x, y, _)| (x, y)).collect();
+    while let Some((x, y)) = stack.pop() {
+        for ny in y.saturating_sub(1)..=(y + 1).min(classes.height() - 1) {
+            for nx in x.saturating_sub(1)..=(x + 1).min(classes.width() - 1) {
+                if classes.get(nx, ny) == Some(EdgeClass::Weak) && edges.get(nx, ny) == Some(false) {
+                    edges.set(nx, ny, Some(true));
+                    stack.push((nx, ny));
+                }
+            }
+        }
+    }
+    edges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suppression_thins_a_ramp_to_one_pixel() {
+        // a vertical edge that rises over a few pixels
+        let src = ImageBuf::from_fn(12, 5, |x, _| ((x as f32 - 3.0) * 50.0).clamp(0.0, 200.0));
+        let canny = Canny { radius: 1, ..Canny::default() };
+        let images = CannyImages::new(&canny, &src, &Border::default());
+        for row in images.suppressed.rows() {
+            let maxima: Vec<usize> = (0..row.len()).filter(|&x| row[x].unwrap() > 0.0).collect();
+            assert_eq!(maxima.len(), 1, "{:?}", row);
+            assert!((4..=6).contains(&maxima[0]), "{:?}", row);
+        }
+    }
+
+    #[test]
+    fn hysteresis_keeps_weak_edges_connected_to_strong_ones() {
+        use EdgeClass::*;
+        let classes = ImageBuf::from_rows(vec![
+            vec![Strong, Weak, NonEdge, NonEdge, NonEdge],
+            vec![NonEdge, NonEdge, Weak, NonEdge, Weak],
+            vec![NonEdge, NonEdge, NonEdge, NonEdge, Weak],
+        ]).unwrap();
+        let edges = hysteresis(&classes.map(Some)).map(|e| e.unwrap());
+        assert_eq!(edges.to_rows(), vec![
+            vec![true, true, false, false, false],
+            vec![false, false, true, false, false],
+            vec![false, false, false, false, false],
+        ]);
+    }
+
+    #[test]
+    fn hysteresis_keeps_skipped_pixels() {
+        let classes = ImageBuf::from_fn(3, 1, |x, _| (x != 1).then_some(EdgeClass::Strong));
+        assert_eq!(hysteresis(&classes).to_rows(), vec![vec![Some(true), None, Some(true)]]);
+    }
+}
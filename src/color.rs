@@ -0,0 +1,124 @@
+pub const CHANNEL_NAMES: [&str; 3] = ["Red", "Green", "Blue"];
+
+/// how the channels are weighted when an rgb image becomes gray
+#[derive(PartialEq, Clone, Copy, Default, serde::Deserialize, serde::Serialize)]
+pub enum LumaWeights {
+    /// the weights of standard definition tv, also used by jpeg
+    #[default]
+    Rec601,
+    /// the weights of hdtv and srgb
+    Rec709,
+    /// the plain mean of the channels
+    Average,
+}
+
+impl LumaWeights {
+    pub const ALL: [LumaWeights; 3] = [LumaWeights::Rec601, LumaWeights::Rec709, LumaWeights::Average];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LumaWeights::Rec601 => "Rec. 601",
+            LumaWeights::Rec709 => "Rec. 709",
+            LumaWeights::Average => "Average",
+        }
+    }
+
+    pub fn weights(&self) -> [f32; 3] {
+        match self {
+            LumaWeights::Rec601 => [0.299, 0.587, 0.114],
+            LumaWeights::Rec709 => [0.2126, 0.7152, 0.0722],
+            LumaWeights::Average => [1.0 / 3.0; 3],
+        }
+    }
+
+    pub fn luma(&self, [r, g, b]: [f32; 3]) -> f32 {
+        let [wr, wg, wb] = self.weights();
+        wr * r + wg * g + wb * b
+    }
+}
+
+/// conversions between color spaces. all the channels stay in 0..=255,
+/// so the converted images can be shown and processed like rgb ones.
+#[derive(PartialEq, Clone, Copy, Default, serde::Deserialize, serde::Serialize)]
+pub enum Conversion {
+    /// hue (0° to 360° mapped to 0 to 255), saturation and value
+    #[default]
+    RgbToHsv,
+    HsvToRgb,
+    /// luma and the two color differences, offset by 128 like in jpeg
+    RgbToYCbCr,
+    YCbCrToRgb,
+    /// luma in all three channels
+    Gray,
+}
+
+impl Conversion {
+    pub const ALL: [Conversion; 5] = [
+        Conversion::RgbToHsv, Conversion::HsvToRgb, Conversion::RgbToYCbCr, Conversion::YCbCrToRgb, Conversion::Gray,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Conversion::RgbToHsv => "RGB → HSV",
+            Conversion::HsvToRgb => "HSV → RGB",
+            Conversion::RgbToYCbCr => "RGB → YCbCr",
+            Conversion::YCbCrToRgb => "YCbCr → RGB",
+            Conversion::Gray => "RGB → gray",
+        }
+    }
+
+    pub fn apply(&self, px: [f32; 3], weights: LumaWeights) -> [f32; 3] {
+        match self {
+            Conversion::RgbToHsv => rgb_to_hsv(px),
+            Conversion::HsvToRgb => hsv_to_rgb(px),
+            Conversion::RgbToYCbCr => {
+                let [r, g, b] = px;
+                [
+                    0.299 * r + 0.587 * g + 0.114 * b,
+                    128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b,
+                    128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b,
+                ]
+            },
+            Conversion::YCbCrToRgb => {
+                let [y, cb, cr] = px;
+                let (cb, cr) = (cb - 128.0, cr - 128.0);
+                [y + 1.402 * cr, y - 0.344136 * cb - 0.714136 * cr, y + 1.772 * cb]
+            },
+            Conversion::Gray => [weights.luma(px); 3],
+        }
+    }
+}
+
+fn rgb_to_hsv([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let hue = if delta <= 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max > 0.0 { delta / max * 255.0 } else { 0.0 };
+    [hue.rem_euclid(360.0) / 360.0 * 255.0, saturation, max]
+}
+
+fn hsv_to_rgb([h, s, v]: [f32; 3]) -> [f32; 3] {
+    let h = (h / 255.0 * 360.0).rem_euclid(360.0) / 60.0;
+    let s = s / 255.0;
+    let c = v * s;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = v - c;
+    [r + m, g + m, b + m]
+}
@@ -0,0 +1,151 @@
+use crate::pix_grid::{ChannelView, PixGrid};
+
+#[derive(PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum ExportFormat {
+    /// lossless grayscale or rgb png of the displayed values, optionally with each cell upscaled to a block
+    Png,
+    /// plain text portable graymap (P2) of the displayed gray levels
+    Pgm,
+    /// matrix of the raw values, one image row per line. rgb grids get a block per channel.
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Png => "png",
+            ExportFormat::Pgm => "pgm",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+/// the largest block a cell can become in a png
+pub const MAX_SCALE: u32 = 32;
+
+/// user settings for exporting a grid
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct ExportSettings {
+    pub format: ExportFormat,
+    /// edge length of the block each cell becomes in a png
+    pub scale: u32,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self { format: ExportFormat::Png, scale: 1 }
+    }
+}
+
+pub fn encode(grid: &PixGrid, settings: &ExportSettings) -> Vec<u8> {
+    match settings.format {
+        ExportFormat::Png => encode_png(grid, settings.scale.clamp(1, MAX_SCALE)),
+        ExportFormat::Pgm => encode_pgm(grid).into_bytes(),
+        ExportFormat::Csv => encode_csv(grid).into_bytes(),
+    }
+}
+
+fn encode_png(grid: &PixGrid, scale: u32) -> Vec<u8> {
+    let map = grid.display_map();
+    let (width, height) = (grid.width() * scale, grid.height() * scale);
+    // a single channel view is shown as gray, so it is saved like that as well
+    let img = if !grid.is_color() || grid.display.view != ChannelView::All {
+        image::DynamicImage::ImageLuma8(image::GrayImage::from_fn(width, height, |x, y| {
+            image::Luma([map.level(grid.pixel(x / scale, y / scale))])
+        }))
+    } else {
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(width, height, |x, y| {
+            let c = map.color(grid.pixel(x / scale, y / scale));
+            image::Rgb([c.r(), c.g(), c.b()])
+        }))
+    };
+    let mut bytes = Vec::new();
+    img.write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageOutputFormat::Png)
+        .expect("encoding a png in memory can't fail");
+    bytes
+}
+
+fn encode_pgm(grid: &PixGrid) -> String {
+    let mut s = format!("P2\n{} {}\n255\n", grid.width(), grid.height());
+    let map = grid.display_map();
+    for iy in 0..grid.height() {
+        // every image row starts a new line, but the format limits lines to 70 characters
+        let mut line_len = 0;
+        for ix in 0..grid.width() {
+            let val = map.level(grid.pixel(ix, iy)).to_string();
+            if line_len > 0 && line_len + 1 + val.len() > 70 {
+                s.push('\n');
+                line_len = 0;
+            }
+            if line_len > 0 {
+                s.push(' ');
+                line_len += 1;
+            }
+            line_len += val.len();
+            s.push_str(&val);
+        }
+        s.push('\n');
+    }
+    s
+}
+
+fn encode_csv(grid: &PixGrid) -> String {
+    let mut s = String::new();
+    // the channels are separated by an empty line
+    for (c, channel) in grid.channel_grids().iter().enumerate() {
+        if c > 0 {
+            s.push('\n');
+        }
+        for iy in 0..channel.height() {
+            let row: Vec<String> = (0..channel.width()).map(|ix| channel.get(ix, iy).to_string()).collect();
+            s.push_str(&row.join(","));
+            s.push('\n');
+        }
+    }
+    s
+}
+
+/// let the user choose where to save the file
+pub fn save(bytes: &[u8], file_name: &str, format: ExportFormat) -> Result<(), String> {
+    let mime_type = match format {
+        ExportFormat::Png => "image/png",
+        ExportFormat::Pgm => "image/x-portable-graymap",
+        ExportFormat::Csv => "text/csv",
+    };
+    save_file(bytes, file_name, format.extension(), mime_type)
+}
+
+/// let the user choose where to save a file with the given extension
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_file(bytes: &[u8], file_name: &str, extension: &str, _mime_type: &str) -> Result<(), String> {
+    let path = rfd::FileDialog::new()
+        .set_file_name(file_name)
+        .add_filter(extension, &[extension])
+        .save_file();
+    match path {
+        Some(path) => std::fs::write(&path, bytes).map_err(|e| format!("{}: {}", path.display(), e)),
+        None => Ok(()),
+    }
+}
+
+/// hand the file to the browser as a download
+#[cfg(target_arch = "wasm32")]
+pub fn save_file(bytes: &[u8], file_name: &str, _extension: &str, mime_type: &str) -> Result<(), String> {
+    use wasm_bindgen::JsCast;
+
+    let err = |e: wasm_bindgen::JsValue| format!("download failed: {:?}", e);
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes));
+    let mut props = web_sys::BlobPropertyBag::new();
+    props.type_(mime_type);
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &props).map_err(err)?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob).map_err(err)?;
+
+    let document = web_sys::window().and_then(|w| w.document()).ok_or("no document")?;
+    let anchor: web_sys::HtmlAnchorElement = document.create_element("a").map_err(err)?
+        .dyn_into().map_err(|_| "not an anchor element")?;
+    anchor.set_href(&url);
+    anchor.set_download(file_name);
+    anchor.click();
+    web_sys::Url::revoke_object_url(&url).map_err(err)
+}
@@ -0,0 +1,480 @@
+use std::f32::consts::PI;
+use std::ops::{Add, Mul, Sub};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::color::LumaWeights;
+use crate::kernel::Convolution;
+use crate::image::ImageBuf;
+use crate::pix_grid::PixGrid;
+
+#[derive(Clone, Copy, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub fn real(re: f32) -> Self {
+        Self { re, im: 0.0 }
+    }
+
+    /// e^(i * angle)
+    pub fn from_angle(angle: f32) -> Self {
+        let (im, re) = angle.sin_cos();
+        Self { re, im }
+    }
+
+    pub fn abs(&self) -> f32 {
+        self.re.hypot(self.im)
+    }
+
+    pub fn arg(&self) -> f32 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn scale(&self, f: f32) -> Self {
+        Self { re: self.re * f, im: self.im * f }
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+    fn add(self, o: Complex) -> Complex {
+        Complex { re: self.re + o.re, im: self.im + o.im }
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+    fn sub(self, o: Complex) -> Complex {
+        Complex { re: self.re - o.re, im: self.im - o.im }
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+    fn mul(self, o: Complex) -> Complex {
+        Complex { re: self.re * o.re - self.im * o.im, im: self.re * o.im + self.im * o.re }
+    }
+}
+
+/// odd lengths up to this are transformed directly, above it bluestein's algorithm is faster
+const MAX_DIRECT_LENGTH: usize = 32;
+
+/// 1d dft without scaling. even lengths are split into two halves like in the radix-2 fft,
+/// what remains is transformed directly if it's short, else with bluestein's algorithm,
+/// so any size takes O(n log n), also primes like 509.
+fn transform(data: &[Complex], inverse: bool) -> Vec<Complex> {
+    let n = data.len();
+    let sign = if inverse { 1.0 } else { -1.0 };
+    if n >= 2 && n % 2 == 0 {
+        let even: Vec<Complex> = data.iter().step_by(2).copied().collect();
+        let odd: Vec<Complex> = data.iter().skip(1).step_by(2).copied().collect();
+        let (even, odd) = (transform(&even, inverse), transform(&odd, inverse));
+        let mut out = vec![Complex::default(); n];
+        for k in 0..n / 2 {
+            let t = Complex::from_angle(sign * 2.0 * PI * k as f32 / n as f32) * odd[k];
+            out[k] = even[k] + t;
+            out[k + n / 2] = even[k] - t;
+        }
+        return out;
+    }
+    if n > MAX_DIRECT_LENGTH {
+        return bluestein(data, inverse);
+    }
+    (0..n).map(|u| {
+        data.iter().enumerate().fold(Complex::default(), |sum, (x, &v)| {
+            // the product is reduced first, large angles would lose precision
+            sum + v * Complex::from_angle(sign * 2.0 * PI * ((u * x) % n) as f32 / n as f32)
+        })
+    }).collect()
+}
+
+/// dft of any length as a convolution with a chirp. since u * x = (u² + x² - (u - x)²) / 2,
+/// the dft is the chirp times the convolution of the chirped data with the conjugate chirp,
+/// which is done with power-of-two transforms.
+fn bluestein(data: &[Complex], inverse: bool) -> Vec<Complex> {
+    let n = data.len();
+    let sign = if inverse { 1.0 } else { -1.0 };
+    // e^(±i π k² / n), with k² reduced first like in the direct dft
+    let chirp: Vec<Complex> = (0..n)
+        .map(|k| Complex::from_angle(sign * PI * ((k * k) % (2 * n)) as f32 / n as f32))
+        .collect();
+    let conj = |c: Complex| Complex { re: c.re, im: -c.im };
+    let m = (2 * n - 1).next_power_of_two();
+    let mut a = vec![Complex::default(); m];
+    for (k, (&v, &c)) in data.iter().zip(&chirp).enumerate() {
+        a[k] = v * c;
+    }
+    // the conjugate chirp for the offsets -(n - 1)..n, negative ones wrapped around
+    let mut b = vec![Complex::default(); m];
+    for (k, &c) in chirp.iter().enumerate() {
+        b[k] = conj(c);
+        b[(m - k) % m] = conj(c);
+    }
+    let (a, b) = (transform(&a, false), transform(&b, false));
+    let product: Vec<Complex> = a.iter().zip(&b).map(|(&a, &b)| a * b).collect();
+    let convolved = transform(&product, true);
+    let f = 1.0 / m as f32;
+    chirp.iter().zip(&convolved).map(|(&c, &v)| c * v.scale(f)).collect()
+}
+
+/// 2d dft of an image given as rows, first along the rows, then along the columns.
+/// the inverse transform is scaled by 1 / (width * height), so it restores the image.
+pub fn dft2(img: &[Vec<Complex>], inverse: bool) -> Vec<Vec<Complex>> {
+    let mut out: Vec<Vec<Complex>> = img.iter().map(|row| transform(row, inverse)).collect();
+    let (w, h) = (out[0].len(), out.len());
+    for x in 0..w {
+        let column: Vec<Complex> = out.iter().map(|row| row[x]).collect();
+        for (row, v) in out.iter_mut().zip(transform(&column, inverse)) {
+            row[x] = v;
+        }
+    }
+    if inverse {
+        let f = 1.0 / (w * h) as f32;
+        out.iter_mut().flatten().for_each(|v| *v = v.scale(f));
+    }
+    out
+}
+
+/// spectrum of the image, indexed by [v][u] with the zero frequency at [0][0]
+pub fn spectrum(img: &ImageBuf<f32>) -> Vec<Vec<Complex>> {
+    let rows: Vec<Vec<Complex>> = img.rows().map(|row| row.iter().map(|&v| Complex::real(v)).collect()).collect();
+    dft2(&rows, false)
+}
+
+/// the spectrum of the luma of the source grid, only recomputed when the source changes
+#[derive(Default)]
+pub struct SpectrumCache {
+    /// generation of the grid and the weights of its luma
+    source: Option<(u64, LumaWeights)>,
+    spectrum: Vec<Vec<Complex>>,
+    /// what the last result of `display` was made from: the source, the revision of the filter, view and centering
+    shown: Option<(u64, LumaWeights, Option<u64>, SpectrumView, bool)>,
+}
+
+impl SpectrumCache {
+    pub fn get(&mut self, grid: &PixGrid, weights: LumaWeights) -> &[Vec<Complex>] {
+        let source = Some((grid.generation(), weights));
+        if self.source != source {
+            self.spectrum = spectrum(grid.to_gray(weights).plane(0));
+            self.source = source;
+        }
+        &self.spectrum
+    }
+
+    /// the gray values of the spectrum view, see `display`. None if nothing changed since the last call.
+    pub fn display(
+        &mut self, grid: &PixGrid, weights: LumaWeights, filter: Option<&FourierFilter>, view: SpectrumView, centered: bool
+    ) -> Option<ImageBuf<f32>> {
+        let shown = Some((grid.generation(), weights, filter.map(|f| f.revision), view, centered));
+        if self.shown == shown {
+            return None;
+        }
+        let img = display(self.get(grid, weights), filter.map(|f| f.mask.as_slice()), view, centered);
+        self.shown = shown;
+        Some(img)
+    }
+}
+
+/// the frequency index shown at a cell of the spectrum view. when centered,
+/// the zero frequency is in the middle and the highest ones are at the edges.
+pub fn freq_idx(ix: u32, iy: u32, width: u32, height: u32, centered: bool) -> (usize, usize) {
+    if centered {
+        (((ix + width - width / 2) % width) as usize, ((iy + height - height / 2) % height) as usize)
+    } else {
+        (ix as usize, iy as usize)
+    }
+}
+
+/// distance of a frequency index from the zero frequency, in cycles per pixel
+fn freq_dist(u: usize, v: usize, width: usize, height: usize) -> f32 {
+    let fu = u.min(width - u) as f32 / width as f32;
+    let fv = v.min(height - v) as f32 / height as f32;
+    fu.hypot(fv)
+}
+
+#[derive(PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum SpectrumView {
+    /// log scaled magnitude
+    Magnitude,
+    Phase,
+    /// magnitude of the frequency filter's transfer function
+    Filter,
+}
+
+/// gray values of the spectrum view
+pub fn display(spectrum: &[Vec<Complex>], filter: Option<&[Vec<Complex>]>, view: SpectrumView, centered: bool) -> ImageBuf<f32> {
+    let (w, h) = (spectrum[0].len() as u32, spectrum.len() as u32);
+    // scaled by the unfiltered maximum, so filtering visibly darkens the spectrum
+    let max = spectrum.iter().flatten().fold(0.0_f32, |m, v| m.max(v.abs()));
+    let log_max = (1.0 + max).ln().max(f32::EPSILON);
+    ImageBuf::from_fn(w, h, |ix, iy| {
+        let (u, v) = freq_idx(ix, iy, w, h, centered);
+        let gain = filter.map_or(Complex::real(1.0), |f| f[v][u]);
+        let value = match view {
+            SpectrumView::Magnitude => (1.0 + (spectrum[v][u] * gain).abs()).ln() / log_max * 255.0,
+            SpectrumView::Phase => (spectrum[v][u].arg() + PI) / (2.0 * PI) * 255.0,
+            SpectrumView::Filter => gain.abs() * 255.0,
+        };
+        value.clamp(0.0, 255.0)
+    })
+}
+
+#[derive(PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum FilterShape {
+    /// a hard cut at the cutoff frequency, causes ringing
+    Ideal,
+    /// smooth transition, which gets steeper with the order
+    Butterworth,
+    Gaussian,
+}
+
+/// which frequencies pass the filter
+#[derive(PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum FilterBand {
+    Low,
+    High,
+    /// the ones around the cutoff
+    Band,
+}
+
+/// source of the mask revisions, shared by all filters so copies with other masks can't have the same one
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct FourierFilter {
+    pub shape: FilterShape,
+    pub band: FilterBand,
+    /// cutoff frequency, or the center of the pass band, in cycles per pixel.
+    /// the highest frequency along an axis is 0.5.
+    pub cutoff: f32,
+    /// width of the pass band, in cycles per pixel
+    pub width: f32,
+    /// order of the butterworth filter
+    pub order: i32,
+    /// add 127 to the result, for filters that remove the mean
+    pub zero_centered: bool,
+    /// gain that painting into the spectrum sets
+    pub paint_gain: f32,
+    pub brush_radius: i32,
+    /// transfer function, indexed by [v][u] with the zero frequency at [0][0].
+    /// it isn't saved, after loading the preset is rebuilt by `ensure_size`.
+    #[serde(skip)]
+    mask: Vec<Vec<Complex>>,
+    /// changes whenever the mask changes, so it doesn't have to be compared
+    #[serde(skip)]
+    revision: u64,
+}
+
+// the mask is compared by its revision
+impl PartialEq for FourierFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.shape == other.shape && self.band == other.band && self.cutoff == other.cutoff
+            && self.width == other.width && self.order == other.order && self.zero_centered == other.zero_centered
+            && self.paint_gain == other.paint_gain && self.brush_radius == other.brush_radius
+            && self.revision == other.revision
+    }
+}
+
+impl Default for FourierFilter {
+    fn default() -> Self {
+        Self {
+            shape: FilterShape::Gaussian,
+            band: FilterBand::Low,
+            cutoff: 0.1,
+            width: 0.1,
+            order: 2,
+            zero_centered: false,
+            paint_gain: 0.0,
+            brush_radius: 1,
+            mask: Vec::new(),
+            revision: 0,
+        }
+    }
+}
+
+impl FourierFilter {
+    /// gain of the preset filter at the given distance from the zero frequency
+    fn gain(&self, d: f32) -> f32 {
+        let d0 = self.cutoff.max(1e-3);
+        let n = self.order as f32;
+        let low_pass = |d0: f32| match self.shape {
+            FilterShape::Ideal => if d <= d0 { 1.0 } else { 0.0 },
+            FilterShape::Butterworth => 1.0 / (1.0 + (d / d0).powf(2.0 * n)),
+            FilterShape::Gaussian => (-d * d / (2.0 * d0 * d0)).exp(),
+        };
+        match self.band {
+            FilterBand::Low => low_pass(d0),
+            FilterBand::High => 1.0 - low_pass(d0),
+            FilterBand::Band => {
+                let w = self.width.max(1e-3);
+                match self.shape {
+                    FilterShape::Ideal => if (d - d0).abs() <= w / 2.0 { 1.0 } else { 0.0 },
+                    // the usual band reject filters, inverted
+                    FilterShape::Butterworth => {
+                        let r = d * w / (d * d - d0 * d0);
+                        if r.is_finite() { 1.0 - 1.0 / (1.0 + r.powf(2.0 * n)) } else { 1.0 }
+                    },
+                    FilterShape::Gaussian => {
+                        let r = (d * d - d0 * d0) / (d * w).max(f32::EPSILON);
+                        (-r * r).exp()
+                    },
+                }
+            },
+        }
+    }
+
+    fn preset_mask(&self, width: usize, height: usize) -> Vec<Vec<Complex>> {
+        (0..height).map(|v| (0..width).map(|u| Complex::real(self.gain(freq_dist(u, v, width, height)))).collect()).collect()
+    }
+
+    /// note that the mask changed
+    fn touch(&mut self) {
+        self.revision = NEXT_REVISION.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// overwrite the mask with the preset filter
+    pub fn apply_preset(&mut self, width: u32, height: u32) {
+        self.mask = self.preset_mask(width as usize, height as usize);
+        self.touch();
+    }
+
+    pub fn fits(&self, width: u32, height: u32) -> bool {
+        self.mask.len() == height as usize && self.mask.iter().all(|row| row.len() == width as usize)
+    }
+
+    /// the mask only makes sense for one image size, on other sizes it is replaced by the preset
+    pub fn ensure_size(&mut self, width: u32, height: u32) {
+        if !self.fits(width, height) {
+            self.apply_preset(width, height);
+        }
+    }
+
+    /// set the mask to the transfer function of a convolution kernel. with wrapping border
+    /// handling, the convolution and the frequency filter give the same result.
+    pub fn set_from_kernel(&mut self, conv: &Convolution, width: u32, height: u32) {
+        let (w, h) = (width as i32, height as i32);
+        self.mask = (0..h).map(|v| (0..w).map(|u| {
+            let mut sum = Complex::default();
+            for y in conv.up..=conv.down {
+                for x in conv.left..=conv.right {
+                    let weight = conv.mask[(y - conv.up) as usize][(x - conv.left) as usize];
+                    // the kernel reads at an offset instead of being flipped, so the exponent is positive
+                    let phase = (u * x).rem_euclid(w) as f32 / w as f32 + (v * y).rem_euclid(h) as f32 / h as f32;
+                    sum = sum + Complex::from_angle(2.0 * PI * phase).scale(weight);
+                }
+            }
+            sum
+        }).collect()).collect();
+        self.zero_centered = conv.zero_centered;
+        self.touch();
+    }
+
+    /// set the gain at a frequency, and at the mirrored one, so the result stays real
+    pub fn paint(&mut self, u: usize, v: usize) {
+        let (w, h) = (self.mask[0].len(), self.mask.len());
+        self.mask[v][u] = Complex::real(self.paint_gain);
+        self.mask[(h - v) % h][(w - u) % w] = Complex::real(self.paint_gain);
+        self.touch();
+    }
+
+    /// filter the image: transform, multiply with the mask and transform back
+    pub fn apply(&self, img: &ImageBuf<f32>) -> ImageBuf<f32> {
+        let rebuilt;
+        let mask = if self.fits(img.width(), img.height()) {
+            &self.mask
+        } else {
+            rebuilt = self.preset_mask(img.width() as usize, img.height() as usize);
+            &rebuilt
+        };
+        let filtered: Vec<Vec<Complex>> = spectrum(img).iter().zip(mask)
+            .map(|(row, m_row)| row.iter().zip(m_row).map(|(&v, &m)| v * m).collect())
+            .collect();
+        let offset = if self.zero_centered { 127.0 } else { 0.0 };
+        let result = dft2(&filtered, true);
+        ImageBuf::from_fn(img.width(), img.height(), |x, y| result[y as usize][x as usize].re + offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: usize, height: usize) -> Vec<Vec<Complex>> {
+        (0..height).map(|y| (0..width).map(|x| Complex::real(((x * 37 + y * 11) % 256) as f32)).collect()).collect()
+    }
+
+    fn assert_close(a: &[Vec<Complex>], b: &[Vec<Complex>]) {
+        for (v, w) in a.iter().flatten().zip(b.iter().flatten()) {
+            assert!((v.re - w.re).abs() < 1e-2 && (v.im - w.im).abs() < 1e-2, "{} {} != {} {}", v.re, v.im, w.re, w.im);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        // powers of two, odd sizes and mixed ones take different paths
+        for (w, h) in [(1, 1), (8, 4), (5, 3), (12, 7)] {
+            let img = image(w, h);
+            assert_close(&dft2(&dft2(&img, false), true), &img);
+        }
+    }
+
+    /// the dft as it's defined, in f64
+    fn naive(data: &[Complex], inverse: bool) -> Vec<Complex> {
+        let n = data.len();
+        let sign = if inverse { 1.0 } else { -1.0 };
+        (0..n).map(|u| {
+            let (re, im) = data.iter().enumerate().fold((0.0, 0.0), |(re, im), (x, v)| {
+                let angle = sign * 2.0 * std::f64::consts::PI * (u * x) as f64 / n as f64;
+                let (s, c) = angle.sin_cos();
+                (re + v.re as f64 * c - v.im as f64 * s, im + v.re as f64 * s + v.im as f64 * c)
+            });
+            Complex { re: re as f32, im: im as f32 }
+        }).collect()
+    }
+
+    #[test]
+    fn bluestein_matches_the_definition() {
+        // 509 is prime, 2 * 33 halves once before bluestein takes over
+        for n in [33, 66, 101, 509] {
+            let data: Vec<Complex> = (0..n).map(|x| Complex { re: ((x * 37) % 256) as f32, im: ((x * 11) % 7) as f32 }).collect();
+            for inverse in [false, true] {
+                let expected = naive(&data, inverse);
+                for (u, (v, w)) in transform(&data, inverse).iter().zip(&expected).enumerate() {
+                    // relative to the size of the sums
+                    let tolerance = 1e-5 * 256.0 * n as f32;
+                    assert!((v.re - w.re).abs() < tolerance && (v.im - w.im).abs() < tolerance, "n {} u {}: {} {} != {} {}", n, u, v.re, v.im, w.re, w.im);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_prime_size() {
+        let img = image(509, 2);
+        assert_close(&dft2(&dft2(&img, false), true), &img);
+    }
+
+    #[test]
+    fn dc_is_the_sum() {
+        let img = image(6, 4);
+        let sum: f32 = img.iter().flatten().map(|v| v.re).sum();
+        let spectrum = dft2(&img, false);
+        assert!((spectrum[0][0].re - sum).abs() < 1e-2);
+        assert!(spectrum[0][0].im.abs() < 1e-2);
+    }
+
+    #[test]
+    fn single_frequency() {
+        // a cosine with one period along x only shows up at u = 1 and u = n - 1
+        let n = 8;
+        let img: Vec<Vec<Complex>> = vec![(0..n).map(|x| Complex::real((2.0 * PI * x as f32 / n as f32).cos())).collect()];
+        let spectrum = dft2(&img, false);
+        for (u, v) in spectrum[0].iter().enumerate() {
+            let expected = if u == 1 || u == n - 1 { n as f32 / 2.0 } else { 0.0 };
+            assert!((v.abs() - expected).abs() < 1e-3, "{} {}", u, v.abs());
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use crate::color::LumaWeights;
+use crate::image::{Border, BorderTap, ImageBuf};
+use crate::kernel::{self, Convolution};
+use crate::pix_grid::PixGrid;
+
+/// pairs of derivative kernels, for the x and the y direction
+#[derive(PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum DerivativePair {
+    Prewitt,
+    Sobel,
+    Scharr,
+}
+
+impl DerivativePair {
+    pub const ALL: [DerivativePair; 3] = [DerivativePair::Prewitt, DerivativePair::Sobel, DerivativePair::Scharr];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DerivativePair::Prewitt => "Prewitt",
+            DerivativePair::Sobel => "Sobel",
+            DerivativePair::Scharr => "Scharr",
+        }
+    }
+
+    /// the kernels for the x and the y derivative. y points up, so the angle is
+    /// counterclockwise like everywhere else.
+    pub fn kernels(&self) -> (Convolution, Convolution) {
+        match self {
+            DerivativePair::Prewitt => (kernel::prewitt_x(), kernel::prewitt_y()),
+            DerivativePair::Sobel => (kernel::sobel_x(), kernel::sobel_y()),
+            DerivativePair::Scharr => (kernel::scharr_x(), kernel::scharr_y()),
+        }
+    }
+
+    /// sum of the positive weights of a kernel, which is what a step from 0 to 1 gives
+    fn weight(&self) -> f32 {
+        match self {
+            DerivativePair::Prewitt => 3.0,
+            DerivativePair::Sobel => 4.0,
+            DerivativePair::Scharr => 16.0,
+        }
+    }
+
+    /// the partial sums, scaled so a step from 0 to 255 gives 255
+    pub fn normalize(&self, gx: f32, gy: f32) -> (f32, f32) {
+        let w = self.weight();
+        (gx / w, gy / w)
+    }
+
+    /// both normalized partial derivatives at x, y
+    pub fn sums(&self, img: &ImageBuf<f32>, x: i32, y: i32, border: &Border) -> Option<(f32, f32)> {
+        let (conv_x, conv_y) = self.kernels();
+        let gx = conv_x.sum_at(img, x, y, border)?;
+        let gy = conv_y.sum_at(img, x, y, border)?;
+        Some(self.normalize(gx, gy))
+    }
+
+    /// both normalized partial derivatives of the luma of a grid at x, y, like `sums` on `grid.to_gray(weights)`.
+    /// only the 3×3 neighbourhood is converted, so hovering over a large rgb grid stays cheap.
+    pub fn luma_sums(&self, grid: &PixGrid, x: i32, y: i32, border: &Border, weights: LumaWeights) -> Option<(f32, f32)> {
+        if !grid.is_color() {
+            return self.sums(grid.plane(0), x, y, border);
+        }
+        let lumas = (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+            .map(|(x, y)| match grid.plane(0).resolve_border(x, y, border) {
+                BorderTap::Pixel(x, y) => Some(weights.luma(grid.pixel(x, y))),
+                BorderTap::Constant(color) => Some(color as f32),
+                BorderTap::Skip => None,
+            })
+            .collect::<Option<Vec<f32>>>()?;
+        // every tap of the kernels lies inside the neighbourhood, so its border is never read
+        let neighbourhood = ImageBuf::from_fn(3, 3, |x, y| lumas[(y * 3 + x) as usize]);
+        self.sums(&neighbourhood, 1, 1, border)
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum GradientOutput {
+    /// euclidean length of the gradient
+    L2,
+    /// sum of the absolute partial derivatives, cheaper and not rotation invariant
+    L1,
+    /// direction of the gradient, 0° to 360° mapped to 0 to 255
+    Orientation,
+    /// the orientation, darkened where the magnitude is low
+    Combined,
+}
+
+impl GradientOutput {
+    pub const ALL: [GradientOutput; 4] = [
+        GradientOutput::L2, GradientOutput::L1, GradientOutput::Orientation, GradientOutput::Combined,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GradientOutput::L2 => "Magnitude (L2)",
+            GradientOutput::L1 => "Magnitude (L1)",
+            GradientOutput::Orientation => "Orientation",
+            GradientOutput::Combined => "Orientation × magnitude",
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Gradient {
+    pub pair: DerivativePair,
+    pub output: GradientOutput,
+    /// multiplies the magnitude, so weak edges become visible
+    pub scale: f32,
+}
+
+impl Default for Gradient {
+    fn default() -> Self {
+        Self { pair: DerivativePair::Sobel, output: GradientOutput::L2, scale: 1.0 }
+    }
+}
+
+/// direction of the gradient in degrees, counterclockwise from the x axis, in [0, 360)
+pub fn angle(gx: f32, gy: f32) -> f32 {
+    gy.atan2(gx).to_degrees().rem_euclid(360.0)
+}
+
+impl Gradient {
+    /// the output value for the normalized partial derivatives
+    pub fn output(&self, gx: f32, gy: f32) -> f32 {
+        let l2 = (gx * gx + gy * gy).sqrt() * self.scale;
+        let orientation = angle(gx, gy) / 360.0 * 255.0;
+        match self.output {
+            GradientOutput::L2 => l2,
+            GradientOutput::L1 => (gx.abs() + gy.abs()) * self.scale,
+            GradientOutput::Orientation => orientation,
+            GradientOutput::Combined => orientation * (l2 / 255.0).min(1.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::BorderMode;
+
+    #[test]
+    fn luma_sums_match_the_gray_grid() {
+        let plane = |f: fn(u32, u32) -> f32| ImageBuf::from_fn(4, 3, f);
+        let grid = PixGrid::from_planes(vec![
+            plane(|x, y| (x * 60 + y * 7) as f32),
+            plane(|_, y| (y * 90) as f32),
+            plane(|x, y| ((x + y) % 2 * 255) as f32),
+        ]);
+        let weights = LumaWeights::Rec709;
+        let gray = grid.to_gray(weights);
+        for mode in [BorderMode::Clamp, BorderMode::Constant, BorderMode::Mirror, BorderMode::Wrap, BorderMode::Skip] {
+            let border = Border { mode, constant: 200 };
+            for (x, y, _) in gray.plane(0).enumerate() {
+                let (x, y) = (x as i32, y as i32);
+                let expected = DerivativePair::Sobel.sums(gray.plane(0), x, y, &border);
+                let actual = DerivativePair::Sobel.luma_sums(&grid, x, y, &border, weights);
+                match (expected, actual) {
+                    (Some(a), Some(b)) => assert!((a.0 - b.0).abs() < 1e-3 && (a.1 - b.1).abs() < 1e-3),
+                    _ => assert_eq!(expected, actual),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,572 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::batch::Op;
+use crate::image::{Border, ImageBuf};
+use crate::kernel::{PresetParams, PRESETS};
+use crate::morphology;
+use crate::pix_grid::{Display, PixGrid, MAX_SIZE};
+
+pub type NodeId = u32;
+
+/// the largest square, stripe width or disc radius of a pattern
+pub const MAX_PERIOD: u32 = 64;
+
+/// a generated test image
+#[derive(PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum Pattern {
+    /// dark on the left, bright on the right
+    Ramp,
+    Checkerboard,
+    /// vertical stripes
+    Stripes,
+    /// a bright disc in the middle, `period` is its radius
+    Disc,
+    /// a single bright pixel in the middle, which shows the kernel of a filter
+    Impulse,
+}
+
+impl Pattern {
+    pub const ALL: [Pattern; 5] = [Pattern::Ramp, Pattern::Checkerboard, Pattern::Stripes, Pattern::Disc, Pattern::Impulse];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pattern::Ramp => "Ramp",
+            Pattern::Checkerboard => "Checkerboard",
+            Pattern::Stripes => "Stripes",
+            Pattern::Disc => "Disc",
+            Pattern::Impulse => "Impulse",
+        }
+    }
+
+    /// a gray image of the pattern, with values between `low` and `high`
+    pub fn generate(&self, width: u32, height: u32, period: u32, low: u8, high: u8) -> ImageBuf<f32> {
+        let period = period.max(1);
+        let (low, high) = (low as f32, high as f32);
+        let (cx, cy) = ((width / 2) as f32, (height / 2) as f32);
+        ImageBuf::from_fn(width, height, |x, y| {
+            let t = match self {
+                Pattern::Ramp => x as f32 / (width - 1).max(1) as f32,
+                Pattern::Checkerboard => ((x / period + y / period) % 2) as f32,
+                Pattern::Stripes => ((x / period) % 2) as f32,
+                Pattern::Disc => {
+                    let (dx, dy) = (x as f32 - cx, y as f32 - cy);
+                    (dx * dx + dy * dy <= (period * period) as f32) as u8 as f32
+                },
+                Pattern::Impulse => (x == width / 2 && y == height / 2) as u8 as f32,
+            };
+            (low + t * (high - low)).round()
+        })
+    }
+}
+
+/// how the arithmetic node combines the values of its two inputs
+#[derive(PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum ArithOp {
+    Add,
+    Subtract,
+    /// a * b / 255, so multiplying with white keeps the image
+    Multiply,
+    /// |a - b|
+    Difference,
+    /// sqrt(a² + b²), combines the x and y derivatives into the gradient magnitude
+    Magnitude,
+    Min,
+    Max,
+}
+
+impl ArithOp {
+    pub const ALL: [ArithOp; 7] = [
+        ArithOp::Add, ArithOp::Subtract, ArithOp::Multiply, ArithOp::Difference,
+        ArithOp::Magnitude, ArithOp::Min, ArithOp::Max,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArithOp::Add => "a + b",
+            ArithOp::Subtract => "a − b",
+            ArithOp::Multiply => "a × b / 255",
+            ArithOp::Difference => "|a − b|",
+            ArithOp::Magnitude => "√(a² + b²)",
+            ArithOp::Min => "min(a, b)",
+            ArithOp::Max => "max(a, b)",
+        }
+    }
+
+    pub fn apply(&self, a: f32, b: f32) -> f32 {
+        match self {
+            ArithOp::Add => a + b,
+            ArithOp::Subtract => a - b,
+            ArithOp::Multiply => a * b / 255.0,
+            ArithOp::Difference => (a - b).abs(),
+            ArithOp::Magnitude => (a * a + b * b).sqrt(),
+            ArithOp::Min => a.min(b),
+            ArithOp::Max => a.max(b),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, serde::Deserialize, serde::Serialize)]
+pub enum NodeKind {
+    /// the source grid of the app
+    Source,
+    Pattern { pattern: Pattern, width: u32, height: u32, period: u32, low: u8, high: u8 },
+    /// convolution with a preset kernel, given by its id like on the command line
+    Conv { preset: String, params: PresetParams, border: Border },
+    /// binary dilation or erosion, see `morphology::boolean_at`
+    Boolean { mask: [[bool; 3]; 3], dilation: bool, threshold: u8, border: Border },
+    Copy,
+    /// op(a, b) * scale + offset
+    Arith { op: ArithOp, scale: f32, offset: f32 },
+    /// shows its input with its own display settings
+    Viewer { display: Display },
+}
+
+impl NodeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NodeKind::Source => "Source image",
+            NodeKind::Pattern { .. } => "Pattern",
+            NodeKind::Conv { .. } => "Convolution",
+            NodeKind::Boolean { .. } => "Boolean operation",
+            NodeKind::Copy => "Copy",
+            NodeKind::Arith { .. } => "Arithmetic",
+            NodeKind::Viewer { .. } => "Viewer",
+        }
+    }
+
+    pub fn input_count(&self) -> usize {
+        match self {
+            NodeKind::Source | NodeKind::Pattern { .. } => 0,
+            NodeKind::Arith { .. } => 2,
+            _ => 1,
+        }
+    }
+
+    /// viewers are the ends of the graph, everything else can feed other nodes
+    pub fn has_output(&self) -> bool {
+        !matches!(self, NodeKind::Viewer { .. })
+    }
+
+    /// the nodes the user can add, with their default parameters
+    pub fn templates() -> Vec<NodeKind> {
+        vec![
+            NodeKind::Source,
+            NodeKind::Pattern { pattern: Pattern::Checkerboard, width: 20, height: 12, period: 4, low: 0, high: 255 },
+            NodeKind::Conv { preset: PRESETS[0].id.to_string(), params: PresetParams::default(), border: Border::default() },
+            NodeKind::Boolean {
+                mask: [[false, true, false], [true, true, true], [false, true, false]],
+                dilation: true,
+                threshold: 127,
+                border: Border::default(),
+            },
+            NodeKind::Copy,
+            NodeKind::Arith { op: ArithOp::Subtract, scale: 1.0, offset: 127.0 },
+            NodeKind::Viewer { display: Display::default() },
+        ]
+    }
+
+    /// bring loaded parameters into the ranges of the editor
+    fn clamp(&mut self) {
+        match self {
+            NodeKind::Pattern { width, height, period, .. } => {
+                *width = (*width).clamp(1, MAX_SIZE);
+                *height = (*height).clamp(1, MAX_SIZE);
+                *period = (*period).clamp(1, MAX_PERIOD);
+            },
+            NodeKind::Conv { params, .. } => params.clamp(),
+            NodeKind::Boolean { threshold, .. } => *threshold = (*threshold).min(morphology::MAX_THRESHOLD),
+            _ => {},
+        }
+    }
+
+    /// the operation of the conv, boolean and copy nodes, which work like the batch binary
+    fn op(&self) -> Result<Option<(Op, Border)>, String> {
+        Ok(match self {
+            NodeKind::Conv { preset, params, border } => {
+                let preset = PRESETS.iter().find(|p| p.id == preset).ok_or(format!("unknown preset: {}", preset))?;
+                Some((Op::Conv((preset.build)(params)), *border))
+            },
+            NodeKind::Boolean { mask, dilation, threshold, border } => {
+                Some((Op::Boolean { mask: *mask, dilation: *dilation, threshold: *threshold }, *border))
+            },
+            NodeKind::Copy => Some((Op::Copy, Border::default())),
+            _ => None,
+        })
+    }
+}
+
+#[derive(PartialEq, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Node {
+    pub kind: NodeKind,
+    /// top left corner in the editor, relative to the canvas
+    pub pos: egui::Pos2,
+    /// the nodes whose outputs are connected to the inputs, only the first `input_count` are used
+    pub inputs: [Option<NodeId>; 2],
+}
+
+/// nodes connected by wires from outputs to inputs. results are computed when a viewer
+/// needs them and kept until the node or something upstream of it changes.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Graph {
+    nodes: BTreeMap<NodeId, Node>,
+    next_id: NodeId,
+    #[serde(skip)]
+    results: HashMap<NodeId, Result<PixGrid, String>>,
+    /// generation and display settings of the source grid the results were computed from
+    #[serde(skip)]
+    source: Option<(u64, Display)>,
+}
+
+impl Default for Graph {
+    /// subtracting the blurred image from the original, which leaves the edges
+    fn default() -> Self {
+        let mut graph = Self { nodes: BTreeMap::new(), next_id: 0, results: HashMap::new(), source: None };
+        let source = graph.add(NodeKind::Source, egui::pos2(20.0, 60.0));
+        let blur = graph.add(NodeKind::Conv {
+            preset: "gaussian".to_string(),
+            params: PresetParams { radius: 2, ..PresetParams::default() },
+            border: Border::default(),
+        }, egui::pos2(200.0, 20.0));
+        let diff = graph.add(NodeKind::Arith { op: ArithOp::Subtract, scale: 1.0, offset: 127.0 }, egui::pos2(480.0, 60.0));
+        let viewer = graph.add(NodeKind::Viewer { display: Display::default() }, egui::pos2(700.0, 60.0));
+        graph.connect(source, blur, 0);
+        graph.connect(source, diff, 0);
+        graph.connect(blur, diff, 1);
+        graph.connect(diff, viewer, 0);
+        graph
+    }
+}
+
+impl Graph {
+    pub fn nodes(&self) -> &BTreeMap<NodeId, Node> {
+        &self.nodes
+    }
+
+    pub fn add(&mut self, kind: NodeKind, pos: egui::Pos2) -> NodeId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.nodes.insert(id, Node { kind, pos, inputs: [None; 2] });
+        id
+    }
+
+    /// remove a node and the wires from its output
+    pub fn remove(&mut self, id: NodeId) {
+        self.invalidate(id);
+        self.nodes.remove(&id);
+        for node in self.nodes.values_mut() {
+            node.inputs.iter_mut().filter(|input| **input == Some(id)).for_each(|input| *input = None);
+        }
+    }
+
+    pub fn set_pos(&mut self, id: NodeId, pos: egui::Pos2) {
+        if let Some(node) = self.nodes.get_mut(&id) {
+            node.pos = pos;
+        }
+    }
+
+    /// change the parameters of a node, which invalidates its result and everything downstream
+    pub fn set_kind(&mut self, id: NodeId, kind: NodeKind) {
+        if self.nodes[&id].kind != kind {
+            self.nodes.get_mut(&id).unwrap().kind = kind;
+            self.invalidate(id);
+        }
+    }
+
+    /// for every node, the nodes its output is wired to
+    fn outputs(&self) -> HashMap<NodeId, Vec<NodeId>> {
+        let mut outputs: HashMap<NodeId, Vec<NodeId>> = HashMap::new();
+        for (&id, node) in &self.nodes {
+            for &input in node.inputs.iter().flatten() {
+                outputs.entry(input).or_default().push(id);
+            }
+        }
+        outputs
+    }
+
+    /// forget the results of a node and of all nodes that depend on it
+    fn invalidate(&mut self, id: NodeId) {
+        // nothing downstream can have a result without this one
+        if !self.results.contains_key(&id) {
+            return;
+        }
+        // an own stack instead of recursion, so long chains can't overflow the call stack
+        let outputs = self.outputs();
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if self.results.remove(&id).is_some() {
+                stack.extend(outputs.get(&id).into_iter().flatten());
+            }
+        }
+    }
+
+    /// whether `id` is `ancestor` or gets its input from it, directly or indirectly
+    fn depends_on(&self, id: NodeId, ancestor: NodeId) -> bool {
+        let mut visited = HashSet::new();
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if id == ancestor {
+                return true;
+            }
+            if visited.insert(id) {
+                if let Some(node) = self.nodes.get(&id) {
+                    stack.extend(node.inputs.iter().flatten());
+                }
+            }
+        }
+        false
+    }
+
+    /// wire the output of `from` to the input `input` of `to`, replacing the wire that was there.
+    /// returns false if the wire would make a cycle.
+    pub fn connect(&mut self, from: NodeId, to: NodeId, input: usize) -> bool {
+        if self.depends_on(from, to) {
+            return false;
+        }
+        self.invalidate(to);
+        self.nodes.get_mut(&to).unwrap().inputs[input] = Some(from);
+        true
+    }
+
+    pub fn disconnect(&mut self, to: NodeId, input: usize) {
+        self.invalidate(to);
+        self.nodes.get_mut(&to).unwrap().inputs[input] = None;
+    }
+
+    /// forget the results that were computed from an older version of the source grid
+    pub fn update_source(&mut self, src: &PixGrid) {
+        let source = Some((src.generation(), src.display));
+        if self.source != source {
+            self.source = source;
+            let sources: Vec<NodeId> = self.nodes.iter()
+                .filter(|(_, node)| node.kind == NodeKind::Source)
+                .map(|(&id, _)| id)
+                .collect();
+            for id in sources {
+                self.invalidate(id);
+            }
+        }
+    }
+
+    /// the output of a node, computing it and what it depends on if necessary
+    pub fn evaluate(&mut self, id: NodeId, src: &PixGrid) -> &Result<PixGrid, String> {
+        // depth first with an own stack: a node stays on it until its inputs have results
+        let mut stack = vec![id];
+        while let Some(&top) = stack.last() {
+            if self.results.contains_key(&top) {
+                stack.pop();
+                continue;
+            }
+            let node = &self.nodes[&top];
+            let inputs = &node.inputs[..node.kind.input_count()];
+            let missing: Vec<NodeId> = inputs.iter().flatten().filter(|input| !self.results.contains_key(input)).copied().collect();
+            if !missing.is_empty() {
+                stack.extend(missing);
+                continue;
+            }
+            stack.pop();
+            let inputs: Result<Vec<&PixGrid>, String> = inputs.iter().enumerate().map(|(i, input)| {
+                match input.map(|input| &self.results[&input]) {
+                    Some(Ok(grid)) => Ok(grid),
+                    Some(Err(_)) => Err(format!("input {} has no image", i + 1)),
+                    None => Err(format!("input {} isn't connected", i + 1)),
+                }
+            }).collect();
+            let result = inputs.and_then(|inputs| compute(&node.kind, &inputs, src));
+            self.results.insert(top, result);
+        }
+        &self.results[&id]
+    }
+
+    /// the output of a node as of the last `evaluate`, None if it wasn't needed since it changed
+    pub fn result(&self, id: NodeId) -> Option<&Result<PixGrid, String>> {
+        self.results.get(&id)
+    }
+
+    /// the graph as text, for saving it to a file
+    pub fn to_ron(&self) -> String {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).expect("graphs can always be serialized")
+    }
+
+    /// read a graph from a file made by `to_ron`
+    pub fn from_ron(text: &str) -> Result<Self, String> {
+        let mut graph: Graph = ron::from_str(text).map_err(|e| e.to_string())?;
+        graph.validate()?;
+        Ok(graph)
+    }
+
+    /// check a loaded graph, whose wires could lead anywhere. the parameters are clamped
+    /// to the ranges of the editor, unused inputs are disconnected.
+    pub fn validate(&mut self) -> Result<(), String> {
+        if self.nodes.keys().any(|&id| id >= self.next_id) {
+            return Err("inconsistent node ids".to_string());
+        }
+        for node in self.nodes.values_mut() {
+            node.kind.clamp();
+            let count = node.kind.input_count();
+            node.inputs[count..].fill(None);
+        }
+        // sort the nodes topologically: take away the nodes without inputs, then the ones
+        // whose inputs are all taken and so on. if some remain, they are on or behind a cycle.
+        let mut pending = HashMap::new();
+        for (&id, node) in &self.nodes {
+            if let Some(input) = node.inputs.iter().flatten().find(|input| !self.nodes.contains_key(input)) {
+                return Err(format!("node {} is connected to the missing node {}", id, input));
+            }
+            pending.insert(id, node.inputs.iter().flatten().count());
+        }
+        let outputs = self.outputs();
+        let mut ready: Vec<NodeId> = pending.iter().filter(|(_, &n)| n == 0).map(|(&id, _)| id).collect();
+        let mut sorted = 0;
+        while let Some(id) = ready.pop() {
+            sorted += 1;
+            for next in outputs.get(&id).into_iter().flatten() {
+                let n = pending.get_mut(next).unwrap();
+                *n -= 1;
+                if *n == 0 {
+                    ready.push(*next);
+                }
+            }
+        }
+        if sorted < self.nodes.len() {
+            return Err("the wires make a cycle".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// the output of a node with the given inputs, which are as many as it has
+fn compute(kind: &NodeKind, inputs: &[&PixGrid], src: &PixGrid) -> Result<PixGrid, String> {
+    if let Some((op, border)) = kind.op()? {
+        let planes = inputs[0].planes().iter().map(|plane| op.apply(plane, &border)).collect();
+        return Ok(PixGrid::from_planes(planes));
+    }
+    match kind {
+        NodeKind::Source => Ok(src.clone()),
+        NodeKind::Pattern { pattern, width, height, period, low, high } => {
+            let (width, height) = ((*width).clamp(1, MAX_SIZE), (*height).clamp(1, MAX_SIZE));
+            Ok(PixGrid::from_planes(vec![pattern.generate(width, height, *period, *low, *high)]))
+        },
+        NodeKind::Arith { op, scale, offset } => {
+            let (a, b) = (inputs[0], inputs[1]);
+            if (a.width(), a.height()) != (b.width(), b.height()) {
+                return Err(format!(
+                    "the inputs have different sizes, {}x{} and {}x{}",
+                    a.width(), a.height(), b.width(), b.height()));
+            }
+            // a gray input is combined with every channel of a color one
+            let channels = a.channel_count().max(b.channel_count());
+            let planes = (0..channels).map(|c| {
+                let (pa, pb) = (a.plane(c.min(a.channel_count() - 1)), b.plane(c.min(b.channel_count() - 1)));
+                ImageBuf::from_fn(a.width(), a.height(), |x, y| op.apply(pa.get(x, y), pb.get(x, y)) * scale + offset)
+            }).collect();
+            Ok(PixGrid::from_planes(planes))
+        },
+        NodeKind::Viewer { display } => {
+            let mut grid = inputs[0].clone();
+            grid.display = *display;
+            Ok(grid)
+        },
+        NodeKind::Conv { .. } | NodeKind::Boolean { .. } | NodeKind::Copy => unreachable!("handled as ops"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a graph with a node of the given kind for every entry, wired as given
+    fn graph(kinds: Vec<NodeKind>, wires: &[(NodeId, NodeId, usize)]) -> Graph {
+        let mut graph = Graph { nodes: BTreeMap::new(), next_id: 0, results: HashMap::new(), source: None };
+        for kind in kinds {
+            graph.add(kind, egui::Pos2::ZERO);
+        }
+        for &(from, to, input) in wires {
+            graph.nodes.get_mut(&to).unwrap().inputs[input] = Some(from);
+        }
+        graph
+    }
+
+    #[test]
+    fn example_is_valid() {
+        let mut graph = Graph::default();
+        assert!(graph.validate().is_ok());
+        assert!(Graph::from_ron(&graph.to_ron()).is_ok());
+    }
+
+    #[test]
+    fn connect_rejects_cycles() {
+        let mut graph = graph(vec![NodeKind::Copy, NodeKind::Copy, NodeKind::Copy], &[]);
+        assert!(graph.connect(0, 1, 0));
+        assert!(graph.connect(1, 2, 0));
+        assert!(!graph.connect(2, 0, 0));
+        assert!(!graph.connect(0, 0, 0));
+    }
+
+    #[test]
+    fn cycle_behind_a_node() {
+        // the cycle between 1 and 2 doesn't go through 0
+        let mut graph = graph(vec![NodeKind::Copy, NodeKind::Copy, NodeKind::Copy], &[(1, 0, 0), (2, 1, 0), (1, 2, 0)]);
+        assert!(graph.validate().is_err());
+        assert!(!graph.depends_on(0, 3));
+        assert!(graph.depends_on(0, 2));
+    }
+
+    #[test]
+    fn long_chain() {
+        // deep enough to overflow the stack if evaluating or invalidating recursed
+        let n = 100_000;
+        let kinds = std::iter::once(NodeKind::Source).chain(std::iter::repeat(NodeKind::Copy).take(n)).collect();
+        let wires: Vec<_> = (0..n as NodeId).map(|id| (id, id + 1, 0)).collect();
+        let mut graph = graph(kinds, &wires);
+        assert!(graph.validate().is_ok());
+        let src = PixGrid::new(2, 2, 50);
+        assert!(graph.evaluate(n as NodeId, &src).is_ok());
+        graph.update_source(&src);
+        graph.update_source(&PixGrid::new(2, 2, 60));
+        assert!(graph.result(n as NodeId).is_none());
+    }
+
+    #[test]
+    fn missing_input() {
+        let mut graph = graph(vec![NodeKind::Copy], &[(5, 0, 0)]);
+        assert!(graph.validate().is_err());
+    }
+
+    #[test]
+    fn inconsistent_ids() {
+        let mut graph = graph(vec![NodeKind::Copy], &[]);
+        graph.next_id = 0;
+        assert!(graph.validate().is_err());
+    }
+
+    #[test]
+    fn unused_inputs_are_disconnected() {
+        let mut graph = graph(vec![NodeKind::Source, NodeKind::Copy], &[(0, 1, 1)]);
+        assert!(graph.validate().is_ok());
+        assert_eq!(graph.nodes[&1].inputs, [None, None]);
+    }
+
+    #[test]
+    fn parameters_are_clamped() {
+        let conv = NodeKind::Conv {
+            preset: "gaussian".to_string(),
+            params: PresetParams { radius: 1000, sigma: f32::NAN, amount: -1.0, angle: 720.0 },
+            border: Border::default(),
+        };
+        let pattern = NodeKind::Pattern { pattern: Pattern::Disc, width: 0, height: 100_000, period: 0, low: 0, high: 255 };
+        let mut graph = graph(vec![conv, pattern], &[]);
+        assert!(graph.validate().is_ok());
+        match &graph.nodes[&0].kind {
+            NodeKind::Conv { params, .. } => {
+                assert_eq!(params.radius, crate::kernel::MAX_CONV_EXTENT);
+                assert_eq!((params.sigma, params.amount, params.angle), (0.2, 0.0, 180.0));
+            },
+            _ => unreachable!(),
+        }
+        match &graph.nodes[&1].kind {
+            NodeKind::Pattern { width, height, period, .. } => assert_eq!((*width, *height, *period), (1, MAX_SIZE, 1)),
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+
+use egui::{Color32, Pos2, Rect, Sense, Stroke};
+
+use crate::export;
+use crate::graph::{ArithOp, Graph, NodeId, NodeKind, Pattern, MAX_PERIOD};
+use crate::grid_view::GridView;
+use crate::image::{Border, BorderMode};
+use crate::import::FileLoader;
+use crate::kernel::{Param, MAX_CONV_EXTENT, PRESETS};
+use crate::morphology;
+use crate::pix_grid::{DisplayMode, PixGrid, MAX_SIZE};
+
+/// room at the sides of a node for its ports
+const PORT_SPACE: f32 = 14.0;
+const PORT_RADIUS: f32 = 5.0;
+/// how close to a port a wire has to be dropped
+const PORT_CATCH: f32 = 12.0;
+const WIRE_COLOR: Color32 = Color32::from_rgb(230, 180, 40);
+/// viewers don't get wider than this, larger images have to be panned
+const MAX_VIEWER_WIDTH: f32 = 320.0;
+
+/// shows a `Graph` as nodes on a canvas, which are moved by dragging their title
+/// and connected by dragging from an output port to an input port
+pub struct GraphEditor {
+    views: HashMap<NodeId, GridView>,
+    /// the node whose output a wire is being dragged from
+    wire_from: Option<NodeId>,
+    loader: FileLoader,
+    error: Option<String>,
+}
+
+impl Default for GraphEditor {
+    fn default() -> Self {
+        Self {
+            views: HashMap::new(),
+            wire_from: None,
+            loader: FileLoader::new("Graphs", &["ron"]),
+            error: None,
+        }
+    }
+}
+
+fn border_combo(ui: &mut egui::Ui, id: NodeId, border: &mut Border) {
+    let name = |mode| match mode {
+        BorderMode::Clamp => "Clamp",
+        BorderMode::Constant => "Constant",
+        BorderMode::Mirror => "Mirror",
+        BorderMode::Wrap => "Wrap",
+        BorderMode::Skip => "Skip",
+    };
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source(("graph_border", id))
+            .selected_text(name(border.mode))
+            .show_ui(ui, |ui| {
+                for mode in [BorderMode::Clamp, BorderMode::Constant, BorderMode::Mirror, BorderMode::Wrap, BorderMode::Skip] {
+                    ui.selectable_value(&mut border.mode, mode, name(mode));
+                }
+            });
+        ui.label("Border");
+        if border.mode == BorderMode::Constant {
+            ui.add(egui::DragValue::new(&mut border.constant).clamp_range(0..=255).prefix("Value: "));
+        }
+    });
+}
+
+/// the parameters of a node, everything except for viewers
+fn params_ui(ui: &mut egui::Ui, id: NodeId, kind: &mut NodeKind) {
+    match kind {
+        NodeKind::Source | NodeKind::Copy | NodeKind::Viewer { .. } => {},
+        NodeKind::Pattern { pattern, width, height, period, low, high } => {
+            egui::ComboBox::from_id_source(("graph_pattern", id))
+                .selected_text(pattern.name())
+                .show_ui(ui, |ui| {
+                    for p in Pattern::ALL {
+                        ui.selectable_value(pattern, p, p.name());
+                    }
+                });
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(width).clamp_range(1..=MAX_SIZE).prefix("Width: "));
+                ui.add(egui::DragValue::new(height).clamp_range(1..=MAX_SIZE).prefix("Height: "));
+            });
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(period).clamp_range(1..=MAX_PERIOD).prefix("Size: "))
+                    .on_hover_text("Width of the squares and stripes, radius of the disc");
+                ui.add(egui::DragValue::new(low).clamp_range(0..=255).prefix("Low: "));
+                ui.add(egui::DragValue::new(high).clamp_range(0..=255).prefix("High: "));
+            });
+        },
+        NodeKind::Conv { preset, params, border } => {
+            let current = PRESETS.iter().find(|p| p.id == preset);
+            egui::ComboBox::from_id_source(("graph_preset", id))
+                .selected_text(current.map_or("Unknown preset", |p| p.name))
+                .show_ui(ui, |ui| {
+                    for p in PRESETS {
+                        if ui.selectable_label(p.id == preset, p.name).clicked() {
+                            *preset = p.id.to_string();
+                        }
+                    }
+                });
+            let used = current.map_or(&[][..], |p| p.params);
+            if used.contains(&Param::Radius) {
+                ui.add(egui::Slider::new(&mut params.radius, 0..=MAX_CONV_EXTENT).text("Radius"));
+            }
+            if used.contains(&Param::Sigma) {
+                ui.add(egui::Slider::new(&mut params.sigma, 0.2..=4.0).text("Sigma"));
+            }
+            if used.contains(&Param::Amount) {
+                ui.add(egui::Slider::new(&mut params.amount, 0.0..=4.0).text("Amount"));
+            }
+            if used.contains(&Param::Angle) {
+                ui.add(egui::Slider::new(&mut params.angle, 0.0..=180.0).text("Angle").suffix("°"));
+            }
+            border_combo(ui, id, border);
+        },
+        NodeKind::Boolean { mask, dilation, threshold, border } => {
+            ui.horizontal(|ui| {
+                ui.selectable_value(dilation, true, "Dilation");
+                ui.selectable_value(dilation, false, "Erosion");
+            });
+            ui.horizontal(|ui| {
+                for ix in 0..=2 {
+                    ui.vertical(|ui| {
+                        for row in mask.iter_mut() {
+                            ui.checkbox(&mut row[ix], "");
+                        }
+                    });
+                }
+                ui.add(egui::DragValue::new(threshold).clamp_range(0..=morphology::MAX_THRESHOLD).prefix("Threshold: "))
+                    .on_hover_text("Values above the threshold count as true");
+            });
+            border_combo(ui, id, border);
+        },
+        NodeKind::Arith { op, scale, offset } => {
+            egui::ComboBox::from_id_source(("graph_arith", id))
+                .selected_text(op.name())
+                .show_ui(ui, |ui| {
+                    for o in ArithOp::ALL {
+                        ui.selectable_value(op, o, o.name());
+                    }
+                });
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(scale).speed(0.01).prefix("× "));
+                ui.add(egui::DragValue::new(offset).speed(1.0).prefix("+ "));
+            });
+        },
+    }
+}
+
+/// a wire from an output to an input, bending out of the ports horizontally
+fn wire(from: Pos2, to: Pos2) -> egui::epaint::CubicBezierShape {
+    let bend = egui::vec2(((to.x - from.x).abs() / 2.0).max(30.0), 0.0);
+    egui::epaint::CubicBezierShape::from_points_stroke(
+        [from, from + bend, to - bend, to], false, Color32::TRANSPARENT, Stroke::new(2.0_f32, WIRE_COLOR))
+}
+
+impl GraphEditor {
+    /// the toolbar and the canvas with the nodes. returns the image of a viewer
+    /// if the user wants it in the target grid.
+    pub fn show(&mut self, ui: &mut egui::Ui, graph: &mut Graph, src: &PixGrid) -> Option<PixGrid> {
+        if let Some(file) = self.loader.poll() {
+            let loaded = file.and_then(|bytes| String::from_utf8(bytes).map_err(|e| e.to_string()))
+                .and_then(|text| Graph::from_ron(&text));
+            match loaded {
+                Ok(loaded) => {
+                    *graph = loaded;
+                    self.views.clear();
+                    self.error = None;
+                },
+                Err(e) => self.error = Some(format!("can't load the graph: {}", e)),
+            }
+        }
+
+        ui.horizontal(|ui| {
+            ui.menu_button("Add node", |ui| {
+                for kind in NodeKind::templates() {
+                    if ui.button(kind.name()).clicked() {
+                        // stagger new nodes so they don't hide each other
+                        let offset = 20.0 * (graph.nodes().len() % 10) as f32;
+                        graph.add(kind, egui::pos2(20.0 + offset, 20.0 + offset));
+                        ui.close_menu();
+                    }
+                }
+            });
+            if ui.button("Save graph…").clicked() {
+                self.error = export::save_file(graph.to_ron().as_bytes(), "graph.ron", "ron", "text/plain").err();
+            }
+            if ui.button("Load graph…").clicked() {
+                self.loader.open_dialog(ui.ctx());
+            }
+            if ui.button("Example").on_hover_text("Replace the graph with the example").clicked() {
+                *graph = Graph::default();
+                self.views.clear();
+            }
+        });
+        ui.label("Drag from an output (right) to an input (left) to connect nodes, click an input to disconnect it.");
+        if let Some(e) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, e);
+        }
+
+        // only what the viewers show is computed
+        graph.update_source(src);
+        let ids: Vec<NodeId> = graph.nodes().keys().copied().collect();
+        for &id in &ids {
+            if matches!(graph.nodes()[&id].kind, NodeKind::Viewer { .. }) {
+                graph.evaluate(id, src);
+            }
+        }
+        self.views.retain(|id, _| graph.nodes().contains_key(id));
+
+        let (canvas, _) = ui.allocate_exact_size(ui.available_size(), Sense::hover());
+        ui.painter().rect_filled(canvas, 4.0, ui.visuals().extreme_bg_color);
+
+        let mut outputs = HashMap::new();
+        let mut inputs = Vec::new();
+        let mut removed = None;
+        let mut to_target = None;
+        for &id in &ids {
+            let node = &graph.nodes()[&id];
+            let mut kind = node.kind.clone();
+            let connected = node.inputs;
+            let area = egui::Area::new(egui::Id::new(("graph_node", id)))
+                .current_pos(canvas.min + node.pos.to_vec2())
+                .constrain_to(canvas)
+                .movable(true)
+                .show(ui.ctx(), |ui| {
+                    let frame = egui::Frame::window(ui.style()).inner_margin(egui::Margin::symmetric(PORT_SPACE, 6.0));
+                    let rect = frame.show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.strong(kind.name());
+                            if ui.small_button("✖").on_hover_text("Remove").clicked() {
+                                removed = Some(id);
+                            }
+                        });
+                        params_ui(ui, id, &mut kind);
+                        if let NodeKind::Viewer { display } = &mut kind {
+                            egui::ComboBox::from_id_source(("graph_display", id))
+                                .selected_text(match display.mode {
+                                    DisplayMode::Clamp => "Clamp",
+                                    DisplayMode::OffsetScale => "Offset + scale",
+                                    DisplayMode::Abs => "Absolute",
+                                    DisplayMode::Normalize => "Normalize",
+                                })
+                                .show_ui(ui, |ui| {
+                                    ui.selectable_value(&mut display.mode, DisplayMode::Clamp, "Clamp");
+                                    ui.selectable_value(&mut display.mode, DisplayMode::OffsetScale, "Offset + scale");
+                                    ui.selectable_value(&mut display.mode, DisplayMode::Abs, "Absolute");
+                                    ui.selectable_value(&mut display.mode, DisplayMode::Normalize, "Normalize");
+                                });
+                            if display.mode == DisplayMode::OffsetScale {
+                                ui.horizontal(|ui| {
+                                    ui.add(egui::DragValue::new(&mut display.offset).speed(1.0).prefix("Offset: "));
+                                    ui.add(egui::DragValue::new(&mut display.scale).speed(0.01).prefix("Scale: "));
+                                });
+                            }
+                        }
+                        match graph.result(id) {
+                            Some(Ok(grid)) if matches!(kind, NodeKind::Viewer { .. }) => {
+                                ui.set_max_width(MAX_VIEWER_WIDTH);
+                                let view = self.views.entry(id).or_insert_with(|| {
+                                    let mut view = GridView::new(8);
+                                    view.margin = 1;
+                                    view
+                                });
+                                view.draw(ui, grid);
+                                // the tools expect the target to have the shape of the source
+                                let fits = grid.same_shape(src);
+                                let button = ui.add_enabled(fits, egui::Button::new("To target"))
+                                    .on_hover_text("Copy the image into the target grid")
+                                    .on_disabled_hover_text("Only images with the size and channels of the source fit into the target grid");
+                                if button.clicked() {
+                                    to_target = Some(grid.clone());
+                                }
+                            },
+                            Some(Err(e)) => {
+                                ui.colored_label(ui.visuals().error_fg_color, e);
+                            },
+                            _ => {},
+                        }
+                    }).response.rect;
+
+                    // the ports, inside the margins of the frame
+                    let port_y = |i: usize| rect.top() + 16.0 + 20.0 * i as f32;
+                    for (i, input) in connected[..kind.input_count()].iter().enumerate() {
+                        let pos = egui::pos2(rect.left() + PORT_SPACE / 2.0, port_y(i));
+                        let response = ui.interact(Rect::from_center_size(pos, egui::Vec2::splat(2.0 * PORT_RADIUS)), ui.id().with(("input", i)), Sense::click());
+                        if response.clicked() && input.is_some() {
+                            graph.disconnect(id, i);
+                        }
+                        let fill = if input.is_some() { WIRE_COLOR } else { Color32::TRANSPARENT };
+                        ui.painter().circle(pos, PORT_RADIUS, fill, Stroke::new(1.5_f32, WIRE_COLOR));
+                        inputs.push((id, i, pos));
+                    }
+                    if kind.has_output() {
+                        let pos = egui::pos2(rect.right() - PORT_SPACE / 2.0, port_y(0));
+                        let response = ui.interact(Rect::from_center_size(pos, egui::Vec2::splat(2.0 * PORT_RADIUS)), ui.id().with("output"), Sense::drag());
+                        if response.drag_started() {
+                            self.wire_from = Some(id);
+                        }
+                        ui.painter().circle(pos, PORT_RADIUS, WIRE_COLOR, Stroke::new(1.5_f32, WIRE_COLOR));
+                        outputs.insert(id, pos);
+                    }
+                });
+            graph.set_pos(id, Pos2::ZERO + (area.response.rect.min - canvas.min));
+            graph.set_kind(id, kind);
+        }
+
+        // wires go below the nodes, which are areas on top of the canvas
+        for &(id, i, to) in &inputs {
+            if let Some(from) = graph.nodes()[&id].inputs[i].and_then(|from| outputs.get(&from)) {
+                ui.painter().add(wire(*from, to));
+            }
+        }
+
+        if let Some(from) = self.wire_from {
+            let pointer = ui.input(|i| i.pointer.interact_pos());
+            if let (Some(start), Some(pointer)) = (outputs.get(&from), pointer) {
+                let painter = ui.ctx().layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("graph_wire")));
+                painter.add(wire(*start, pointer));
+            }
+            if !ui.input(|i| i.pointer.primary_down()) {
+                self.wire_from = None;
+                let target = inputs.iter().find(|(_, _, pos)| pointer.is_some_and(|p| p.distance(*pos) < PORT_CATCH));
+                if let Some(&(to, i, _)) = target {
+                    self.error = if graph.connect(from, to, i) {
+                        None
+                    } else {
+                        Some("that wire would make a cycle".to_string())
+                    };
+                }
+            }
+        }
+
+        if let Some(id) = removed {
+            graph.remove(id);
+        }
+        to_target
+    }
+}
@@ -0,0 +1,396 @@
+use egui::{Sense, Rounding, Color32, Stroke, Pos2, Rect, PointerButton};
+
+use crate::color::LumaWeights;
+use crate::pix_grid::{format_value, ChannelView, Display, DisplayMap, PixGrid};
+
+/// the view of a grid doesn't grow higher than this, larger content has to be panned
+const MAX_VIEW_HEIGHT: f32 = 400.0;
+const MIN_ZOOM: f32 = 0.1;
+const MAX_ZOOM: f32 = 10.0;
+
+/// shows a `PixGrid` as cells and lets the user point at them.
+/// the grid is passed to every call, the view only keeps how it is shown.
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct GridView {
+    pub pix_size: u32,
+    pub margin: u32,
+    /// write the value of each cell into it
+    #[serde(default)]
+    pub show_values: bool,
+    /// magnification of `pix_size` and `margin`, changed with the mouse wheel
+    #[serde(default = "default_zoom")]
+    zoom: f32,
+    /// offset of the content in the view, changed by dragging with the middle button or with shift
+    #[serde(default)]
+    pan: egui::Vec2,
+    // the interaction state is only valid for the current frame, so it isn't persisted
+    #[serde(skip)]
+    hovered_idx: Option<(u32, u32)>,
+    #[serde(skip)]
+    pressed: bool,
+    /// zoom or pan were changed by the user in this frame
+    #[serde(skip)]
+    view_changed: bool,
+    /// set the zoom so the whole grid fits into the view the next time it's drawn
+    #[serde(skip)]
+    fit_requested: bool,
+    /// the content, which may be larger than the view or moved by panning
+    #[serde(skip, default = "empty_rect")]
+    rect: Rect,
+    /// the part of the screen the grid is drawn into
+    #[serde(skip, default = "empty_rect")]
+    viewport: Rect,
+    #[serde(skip)]
+    texture: GridTexture,
+}
+
+/// the colors of the cells, with one texel per cell. it is only updated when the pixels
+/// or the display settings change, so drawing a large grid doesn't paint every cell.
+#[derive(Default)]
+struct GridTexture {
+    handle: Option<egui::TextureHandle>,
+    /// generation of the grid and display settings the texture was made with
+    made_from: Option<(u64, Display)>,
+}
+
+// a copy of a view gets its own texture, so updating it doesn't change the original's
+impl Clone for GridTexture {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+fn default_zoom() -> f32 { 1.0 }
+
+/// black or white, whichever is easier to read on the given color
+fn contrast_color(color: Color32) -> Color32 {
+    let luma = LumaWeights::Rec601.luma([color.r() as f32, color.g() as f32, color.b() as f32]);
+    if luma > 127.0 { Color32::BLACK } else { Color32::WHITE }
+}
+
+const OUTLINE_COLOR: Color32 = Color32::from_rgb(20, 200, 20);
+const BACKGROUND_COLOR: Color32 = Color32::from_gray(20);
+
+fn empty_rect() -> Rect {
+    Rect::from_min_max(Pos2::new(0.0, 0.0), Pos2::new(0.0, 0.0))
+}
+
+impl Default for GridView {
+    fn default() -> Self {
+        Self::new(16)
+    }
+}
+
+impl GridView {
+    pub fn new(pix_size: u32) -> Self {
+        Self {
+            pix_size,
+            margin: 3,
+            show_values: false,
+            zoom: 1.0,
+            pan: egui::Vec2::ZERO,
+            hovered_idx: None,
+            pressed: false,
+            view_changed: false,
+            fit_requested: false,
+            rect: empty_rect(),
+            viewport: empty_rect(),
+            texture: GridTexture::default(),
+        }
+    }
+
+    pub fn draw_outline(&self, ui: &mut egui::Ui, grid: &PixGrid, from_ix: u32, from_iy: u32, to_ix: u32, to_iy: u32) {
+        let painter = self.painter(ui);
+        // instead of drawing invividual lines, draw a rect in the background...
+        let (step, gap) = (self.step(), self.gap());
+        let br_rect = Rect::from_min_max(
+            self.rect.min + egui::vec2(from_ix as f32 * step, from_iy as f32 * step),
+            self.rect.min + egui::vec2((to_ix + 1) as f32 * step + gap, (to_iy + 1) as f32 * step + gap));
+        painter.rect(br_rect, Rounding::ZERO, OUTLINE_COLOR, Stroke::NONE);
+
+        // then redraw the boxes on top
+        let map = grid.display_map();
+        for iy in from_iy..=to_iy {
+            for ix in from_ix..=to_ix {
+                self.paint_pixel(&painter, grid, &map, ix, iy, grid.pixel(ix, iy));
+            }
+        }
+        // without a margin, the background doesn't show
+        if gap < 1.0 {
+            painter.rect_stroke(br_rect, Rounding::ZERO, Stroke::new(2.0_f32, OUTLINE_COLOR));
+        }
+    }
+
+    pub fn draw_outline_clamped(&self, ui: &mut egui::Ui, grid: &PixGrid, from_ix: i32, from_iy: i32, to_ix: i32, to_iy: i32) {
+        let from_ix = from_ix.clamp(0, grid.width() as i32 - 1) as u32;
+        let from_iy = from_iy.clamp(0, grid.height() as i32 - 1) as u32;
+        let to_ix = to_ix.clamp(0, grid.width() as i32 - 1) as u32;
+        let to_iy = to_iy.clamp(0, grid.height() as i32 - 1) as u32;
+        self.draw_outline(ui, grid, from_ix, from_iy, to_ix, to_iy);
+    }
+
+    /// draw a pixel, e.g. a preview of a tool's result, with the display mapping of the grid.
+    /// `map` is `grid.display_map()`, which callers look up once instead of for every cell.
+    pub fn try_draw_pixel_at_idx(&self, ui: &mut egui::Ui, grid: &PixGrid, map: &DisplayMap, ix: i32, iy: i32, px: [f32; 3]) {
+        if grid.in_bounds(ix, iy) {
+            self.paint_pixel(&self.painter(ui), grid, map, ix as u32, iy as u32, px);
+        }
+    }
+
+    /// draw a highlighted cell at any index, also outside of the image, e.g. to show which values
+    /// a neighbourhood reads beyond the border. None marks the value as invalid with a cross.
+    /// `map` is `grid.display_map()`, see `try_draw_pixel_at_idx`.
+    pub fn draw_cell_at_idx(&self, ui: &mut egui::Ui, grid: &PixGrid, map: &DisplayMap, ix: i32, iy: i32, px: Option<[f32; 3]>) {
+        let (step, gap) = (self.step(), self.gap());
+        let min = self.rect.min + egui::vec2(ix as f32 * step, iy as f32 * step);
+        let outer = Rect::from_min_size(min, egui::vec2(step + gap, step + gap));
+        let inner = self.cell_rect(ix, iy);
+        let painter = &self.painter(ui);
+        painter.rect(outer, Rounding::ZERO, Color32::from_rgb(230, 140, 20), Stroke::NONE);
+        match px {
+            Some(px) => {
+                let color = map.color(px);
+                painter.rect(inner, Rounding::ZERO, color, Stroke::NONE);
+                if self.show_values {
+                    self.draw_text(painter, inner, &value_text(grid, px), contrast_color(color));
+                }
+            },
+            None => {
+                painter.rect(inner, Rounding::ZERO, BACKGROUND_COLOR, Stroke::NONE);
+                let stroke = Stroke::new(2.0_f32, Color32::from_rgb(220, 30, 30));
+                painter.line_segment([inner.left_top(), inner.right_bottom()], stroke);
+                painter.line_segment([inner.right_top(), inner.left_bottom()], stroke);
+            },
+        }
+    }
+
+    /// screen position of the center of a cell, which may be outside of the grid
+    pub fn cell_center(&self, ix: i32, iy: i32) -> egui::Pos2 {
+        self.cell_rect(ix, iy).center()
+    }
+
+    /// distance between neighbouring cells on the screen
+    fn step(&self) -> f32 {
+        (self.pix_size + self.margin) as f32 * self.zoom
+    }
+
+    /// width of the margin on the screen
+    fn gap(&self) -> f32 {
+        self.margin as f32 * self.zoom
+    }
+
+    /// size of all the cells and margins of the grid on the screen, at the current zoom
+    fn content_size(&self, grid: &PixGrid) -> egui::Vec2 {
+        egui::vec2(grid.width() as f32 * self.step() + self.gap(), grid.height() as f32 * self.step() + self.gap())
+    }
+
+    /// screen rect of a cell without its margin, which may be outside of the grid
+    fn cell_rect(&self, ix: i32, iy: i32) -> Rect {
+        let (step, gap) = (self.step(), self.gap());
+        let min = self.rect.min + egui::vec2(ix as f32 * step + gap, iy as f32 * step + gap);
+        Rect::from_min_size(min, egui::vec2(step - gap, step - gap))
+    }
+
+    /// a painter that doesn't draw outside of the view where the content is cut off.
+    /// along axes where it fits, cells outside of the image, like border taps, stay visible.
+    fn painter(&self, ui: &egui::Ui) -> egui::Painter {
+        let outer = ui.clip_rect();
+        let mut clip = self.viewport;
+        if self.rect.width() <= self.viewport.width() + 0.5 {
+            (clip.min.x, clip.max.x) = (outer.min.x, outer.max.x);
+        }
+        if self.rect.height() <= self.viewport.height() + 0.5 {
+            (clip.min.y, clip.max.y) = (outer.min.y, outer.max.y);
+        }
+        ui.painter().with_clip_rect(clip.intersect(outer))
+    }
+
+    pub fn draw_rect_at_idx(&self, ui: &mut egui::Ui, ix: u32, iy: u32, color: Color32) {
+        self.painter(ui).rect(self.cell_rect(ix as i32, iy as i32), Rounding::ZERO, color, Stroke::NONE);
+    }
+
+    /// draw the box of a pixel and, if enabled, its value
+    fn paint_pixel(&self, painter: &egui::Painter, grid: &PixGrid, map: &DisplayMap, ix: u32, iy: u32, px: [f32; 3]) {
+        let color = map.color(px);
+        let cell = self.cell_rect(ix as i32, iy as i32);
+        painter.rect(cell, Rounding::ZERO, color, Stroke::NONE);
+        if self.show_values {
+            self.draw_text(painter, cell, &value_text(grid, px), contrast_color(color));
+        }
+    }
+
+    /// write text into a cell, as large as fits. nothing is drawn if it would be unreadably small.
+    fn draw_text(&self, painter: &egui::Painter, cell: Rect, text: &str, color: Color32) {
+        let lines = text.lines().count().max(1) as f32;
+        let chars = text.lines().map(|l| l.chars().count()).max().unwrap_or(1).max(1) as f32;
+        // monospace glyphs are about 0.6 times as wide as the font size
+        let size = (cell.width() * 0.95 / (0.6 * chars)).min(cell.height() * 0.9 / lines).min(cell.height() * 0.6);
+        if size < 6.0 {
+            return;
+        }
+        painter.text(cell.center(), egui::Align2::CENTER_CENTER, text, egui::FontId::monospace(size), color);
+    }
+
+    /// write a short note, like a kernel weight, into the lower part of a cell at any index.
+    /// it gets its own background, so it stays readable on top of the value.
+    pub fn draw_annotation_at_idx(&self, ui: &mut egui::Ui, ix: i32, iy: i32, text: &str) {
+        let cell = self.cell_rect(ix, iy);
+        let band = if self.show_values {
+            Rect::from_min_max(Pos2::new(cell.min.x, cell.max.y - cell.height() * 0.4), cell.max)
+        } else {
+            cell
+        };
+        let painter = self.painter(ui);
+        painter.rect(band, Rounding::ZERO, Color32::from_black_alpha(160), Stroke::NONE);
+        self.draw_text(&painter, band, text, Color32::from_rgb(255, 170, 40));
+    }
+
+    /// draw the grid into a view that fits the available width and scrolls larger content.
+    /// the mouse wheel zooms, dragging with the middle button or with shift pans.
+    pub fn draw(&mut self, ui: &mut egui::Ui, grid: &PixGrid) {
+        let available = egui::vec2(ui.available_width().max(1.0), MAX_VIEW_HEIGHT);
+        self.view_changed = false;
+        if self.fit_requested {
+            let unzoomed = self.content_size(grid) / self.zoom;
+            self.zoom = (available.x / unzoomed.x).min(available.y / unzoomed.y).clamp(MIN_ZOOM, MAX_ZOOM);
+            self.pan = egui::Vec2::ZERO;
+            self.fit_requested = false;
+            self.view_changed = true;
+        }
+        let view_size = self.content_size(grid).min(available);
+        let (viewport, response) = ui.allocate_exact_size(view_size, Sense::click_and_drag());
+        self.viewport = viewport;
+
+        if let Some(pos) = response.hover_pos() {
+            let (scroll, zoom_delta) = ui.input(|i| (i.scroll_delta.y, i.zoom_delta()));
+            let factor = (scroll / 200.0).exp() * zoom_delta;
+            if factor != 1.0 {
+                // keep the point under the pointer in place
+                let zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+                let anchor = (pos - (viewport.min + self.pan)) / self.zoom;
+                self.pan = pos - anchor * zoom - viewport.min;
+                self.zoom = zoom;
+                self.view_changed = true;
+            }
+        }
+        let shift = ui.input(|i| i.modifiers.shift);
+        if response.dragged_by(PointerButton::Middle) || (shift && response.dragged_by(PointerButton::Primary)) {
+            self.pan += response.drag_delta();
+            self.view_changed = true;
+        }
+        // the content can only be moved as far as it is larger than the view
+        let content = self.content_size(grid);
+        self.pan.x = if content.x <= view_size.x { 0.0 } else { self.pan.x.clamp(view_size.x - content.x, 0.0) };
+        self.pan.y = if content.y <= view_size.y { 0.0 } else { self.pan.y.clamp(view_size.y - content.y, 0.0) };
+        self.rect = Rect::from_min_size(viewport.min + self.pan, content);
+
+        // check for hover and mouse press (button down, not just click so tools can be dragged)
+        match response.hover_pos().filter(|&pos| self.rect.contains(pos)) {
+            Some(pos) => {
+                let pos = pos - self.rect.min;
+                // clamping in case the pointer is on the last margin
+                let ix = ((pos.x / self.step()) as u32).min(grid.width() - 1);
+                let iy = ((pos.y / self.step()) as u32).min(grid.height() - 1);
+                self.hovered_idx = Some((ix, iy));
+                let primary = ui.input(|i| i.pointer.primary_down());
+                self.pressed = response.is_pointer_button_down_on() && primary && !shift;
+            },
+            None => {
+                self.hovered_idx = None;
+                self.pressed = false;
+            },
+        }
+
+        if ui.is_rect_visible(viewport) {
+            let texture = self.texture_id(ui.ctx(), grid);
+            // first, draw the background:
+            let painter = self.painter(ui);
+            painter.rect(viewport, Rounding::ZERO, BACKGROUND_COLOR, Stroke::NONE);
+
+            // the texel of a cell also covers the margin to its right and below,
+            // the margins are drawn over it as lines
+            let (step, gap) = (self.step(), self.gap());
+            let uv = Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0));
+            painter.image(texture, Rect::from_min_max(self.rect.min + egui::vec2(gap, gap), self.rect.max), uv, Color32::WHITE);
+
+            let first = |offset: f32| (offset / step).floor().max(0.0) as u32;
+            let (x0, y0) = (first(-self.pan.x), first(-self.pan.y));
+            let x1 = (first(view_size.x - self.pan.x) + 1).min(grid.width());
+            let y1 = (first(view_size.y - self.pan.y) + 1).min(grid.height());
+            if gap >= 0.5 {
+                for ix in x0..=x1 {
+                    let line = Rect::from_min_size(self.rect.min + egui::vec2(ix as f32 * step, 0.0), egui::vec2(gap, content.y));
+                    painter.rect(line, Rounding::ZERO, BACKGROUND_COLOR, Stroke::NONE);
+                }
+                for iy in y0..=y1 {
+                    let line = Rect::from_min_size(self.rect.min + egui::vec2(0.0, iy as f32 * step), egui::vec2(content.x, gap));
+                    painter.rect(line, Rounding::ZERO, BACKGROUND_COLOR, Stroke::NONE);
+                }
+            }
+
+            // the values of the cells that are in view and large enough for text
+            if self.show_values && step - gap >= 10.0 {
+                let map = grid.display_map();
+                for iy in y0..y1 {
+                    for ix in x0..x1 {
+                        let px = grid.pixel(ix, iy);
+                        self.draw_text(&painter, self.cell_rect(ix as i32, iy as i32), &value_text(grid, px), contrast_color(map.color(px)));
+                    }
+                }
+            }
+        }
+    }
+
+    /// the texture with the colors of the cells, updated if the pixels or the display settings changed
+    fn texture_id(&mut self, ctx: &egui::Context, grid: &PixGrid) -> egui::TextureId {
+        let made_from = Some((grid.generation(), grid.display));
+        if self.texture.made_from != made_from || self.texture.handle.is_none() {
+            let map = grid.display_map();
+            let pixels = (0..grid.height())
+                .flat_map(|iy| (0..grid.width()).map(move |ix| (ix, iy)))
+                .map(|(ix, iy)| map.color(grid.pixel(ix, iy)))
+                .collect();
+            let image = egui::ColorImage { size: [grid.width() as usize, grid.height() as usize], pixels };
+            match &mut self.texture.handle {
+                Some(handle) => handle.set(image, egui::TextureOptions::NEAREST),
+                None => self.texture.handle = Some(ctx.load_texture("pix_grid", image, egui::TextureOptions::NEAREST)),
+            }
+            self.texture.made_from = made_from;
+        }
+        self.texture.handle.as_ref().expect("the texture was just created").id()
+    }
+
+    pub fn zoom(&self) -> f32 { self.zoom }
+
+    pub fn set_zoom(&mut self, zoom: f32) {
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+
+    /// set the zoom so the whole grid fits into the view the next time it's drawn
+    pub fn fit_to_view(&mut self) {
+        self.fit_requested = true;
+    }
+
+    /// whether the user zoomed or panned while the grid was drawn the last time
+    pub fn view_changed(&self) -> bool { self.view_changed }
+
+    /// show the grid like another view, so the same cells are in the same place
+    pub fn copy_view_from(&mut self, other: &GridView) {
+        self.pix_size = other.pix_size;
+        self.margin = other.margin;
+        self.zoom = other.zoom;
+        self.pan = other.pan;
+    }
+
+    pub fn pressed(&self) -> bool { self.pressed }
+    pub fn hovered_idx(&self) -> Option<(u32, u32)> { self.hovered_idx }
+}
+
+/// the values of a pixel as they are written into its cell. rgb pixels get a line per channel.
+fn value_text(grid: &PixGrid, px: [f32; 3]) -> String {
+    match (grid.is_color(), grid.display.view) {
+        (true, ChannelView::All) => px.map(|v| format_value(v, 1)).join("\n"),
+        (true, ChannelView::Channel(c)) => format_value(px[c.min(2)], 1),
+        (false, _) => format_value(px[0], 1),
+    }
+}
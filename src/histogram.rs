@@ -0,0 +1,129 @@
+use eframe::egui;
+use egui::{Color32, Pos2, Rect, Rounding, Sense, Stroke};
+
+use crate::pix_grid::{Display, PixGrid};
+
+#[derive(Clone)]
+pub struct Histogram {
+    pub counts: [u32; 256],
+    pub total: u32,
+}
+
+impl Histogram {
+    /// histogram of the displayed gray levels, the luma for rgb grids
+    pub fn of(grid: &PixGrid) -> Self {
+        let map = grid.display_map();
+        let mut counts = [0; 256];
+        for iy in 0..grid.height() {
+            for ix in 0..grid.width() {
+                counts[map.level(grid.pixel(ix, iy)) as usize] += 1;
+            }
+        }
+        Self { counts, total: grid.width() * grid.height() }
+    }
+
+    /// number of pixels with a value up to and including the index
+    pub fn cumulative(&self) -> [u32; 256] {
+        let mut cdf = [0; 256];
+        let mut sum = 0;
+        for (c, &n) in cdf.iter_mut().zip(self.counts.iter()) {
+            sum += n;
+            *c = sum;
+        }
+        cdf
+    }
+
+    /// smallest value that at least the given percentage of pixels is at or below
+    pub fn percentile(&self, percent: f32) -> u8 {
+        let needed = (percent / 100.0 * self.total as f32).ceil().max(1.0) as u32;
+        self.cumulative().iter().position(|&c| c >= needed).unwrap_or(255) as u8
+    }
+
+    /// lookup table that spreads the values so the cumulative histogram becomes a straight line
+    pub fn equalize_lut(&self) -> [u8; 256] {
+        let cdf = self.cumulative();
+        // the lowest occurring value should map to 0
+        let cdf_min = cdf.iter().copied().find(|&c| c > 0).unwrap_or(0);
+        let mut lut = [0; 256];
+        if self.total == cdf_min {
+            // only one value, there's nothing to spread
+            return std::array::from_fn(|v| v as u8);
+        }
+        for (v, l) in lut.iter_mut().enumerate() {
+            let c = cdf[v].saturating_sub(cdf_min) as f32 / (self.total - cdf_min) as f32;
+            *l = (c * 255.0).round() as u8;
+        }
+        lut
+    }
+
+    /// lookup table that maps the values at the given percentiles to 0 and 255, linearly in between
+    pub fn stretch_lut(&self, low_percent: f32, high_percent: f32) -> [u8; 256] {
+        let low = self.percentile(low_percent) as f32;
+        let high = (self.percentile(high_percent) as f32).max(low + 1.0);
+        std::array::from_fn(|v| ((v as f32 - low) / (high - low) * 255.0).round().clamp(0.0, 255.0) as u8)
+    }
+
+    /// lookup table that gives this histogram the shape of the reference histogram:
+    /// each value maps to the smallest reference value with at least the same cumulative share
+    pub fn match_lut(&self, reference: &Histogram) -> [u8; 256] {
+        let cdf = self.cumulative();
+        let ref_cdf = reference.cumulative();
+        std::array::from_fn(|v| {
+            let share = cdf[v] as f64 / self.total as f64;
+            ref_cdf.iter()
+                .position(|&c| c as f64 / reference.total as f64 >= share - 1e-9)
+                .unwrap_or(255) as u8
+        })
+    }
+
+    /// draw the histogram as bars, and the cumulative histogram as a line on top
+    pub fn draw(&self, ui: &mut egui::Ui, size: egui::Vec2) {
+        let (rect, _) = ui.allocate_exact_size(size, Sense::hover());
+        if !ui.is_rect_visible(rect) {
+            return;
+        }
+        let painter = ui.painter();
+        painter.rect(rect, Rounding::ZERO, Color32::from_gray(20), Stroke::NONE);
+
+        let bar_width = rect.width() / 256.0;
+        let max = self.counts.iter().copied().max().unwrap_or(0).max(1) as f32;
+        for (v, &n) in self.counts.iter().enumerate() {
+            if n == 0 {
+                continue;
+            }
+            let x = rect.min.x + v as f32 * bar_width;
+            let h = n as f32 / max * rect.height();
+            painter.rect(
+                Rect::from_min_max(Pos2::new(x, rect.max.y - h), Pos2::new(x + bar_width.max(1.0), rect.max.y)),
+                Rounding::ZERO,
+                Color32::from_gray(200),
+                Stroke::NONE);
+        }
+
+        let cdf = self.cumulative();
+        let points = cdf.iter().enumerate().map(|(v, &c)| Pos2::new(
+            rect.min.x + (v as f32 + 0.5) * bar_width,
+            rect.max.y - c as f32 / self.total.max(1) as f32 * rect.height(),
+        )).collect();
+        painter.add(egui::Shape::line(points, Stroke::new(1.5_f32, Color32::from_rgb(20, 200, 20))));
+    }
+}
+
+/// the histogram of a grid, only recomputed when its pixels or display settings change
+#[derive(Default)]
+pub struct HistogramCache {
+    /// generation and display settings of the grid the histogram was made from
+    source: Option<(u64, Display)>,
+    histogram: Option<Histogram>,
+}
+
+impl HistogramCache {
+    pub fn get(&mut self, grid: &PixGrid) -> &Histogram {
+        let source = Some((grid.generation(), grid.display));
+        if self.source != source || self.histogram.is_none() {
+            self.histogram = Some(Histogram::of(grid));
+            self.source = source;
+        }
+        self.histogram.as_ref().unwrap()
+    }
+}
@@ -0,0 +1,83 @@
+use crate::pix_grid::PixGrid;
+
+/// how many steps can be undone. the grids are small, so whole snapshots are fine.
+const MAX_STEPS: usize = 100;
+/// large grids are limited to fewer steps, so the snapshots don't hold more values than this
+const MAX_VALUES: usize = 32 * 1024 * 1024;
+
+struct Snapshot {
+    src: PixGrid,
+    dst: PixGrid,
+}
+
+impl Snapshot {
+    fn take(src: &PixGrid, dst: &PixGrid) -> Self {
+        Self { src: src.clone(), dst: dst.clone() }
+    }
+
+    fn matches(&self, src: &PixGrid, dst: &PixGrid) -> bool {
+        self.src.same_pixels(src) && self.dst.same_pixels(dst)
+    }
+
+    fn value_count(&self) -> usize {
+        self.src.value_count() + self.dst.value_count()
+    }
+
+    fn restore(&self, src: &mut PixGrid, dst: &mut PixGrid) {
+        src.copy_pixels_from(&self.src);
+        dst.copy_pixels_from(&self.dst);
+    }
+}
+
+/// undo/redo history of the source and target grid.
+/// an edit is enclosed by `begin_edit` and `end_edit`, so a whole pen stroke becomes one step.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    // state from before the edit that is currently in progress
+    pending: Option<Snapshot>,
+}
+
+impl History {
+    /// remember the current state as the start of an edit.
+    /// does nothing if an edit is already in progress.
+    pub fn begin_edit(&mut self, src: &PixGrid, dst: &PixGrid) {
+        if self.pending.is_none() {
+            self.pending = Some(Snapshot::take(src, dst));
+        }
+    }
+
+    /// finish the current edit. it only becomes an undo step if it changed anything.
+    pub fn end_edit(&mut self, src: &PixGrid, dst: &PixGrid) {
+        if let Some(before) = self.pending.take() {
+            if !before.matches(src, dst) {
+                self.undo.push(before);
+                while self.undo.len() > MAX_STEPS
+                    || (self.undo.len() > 1 && self.undo.iter().map(Snapshot::value_count).sum::<usize>() > MAX_VALUES) {
+                    self.undo.remove(0);
+                }
+                self.redo.clear();
+            }
+        }
+    }
+
+    pub fn undo(&mut self, src: &mut PixGrid, dst: &mut PixGrid) {
+        self.end_edit(src, dst);
+        if let Some(s) = self.undo.pop() {
+            self.redo.push(Snapshot::take(src, dst));
+            s.restore(src, dst);
+        }
+    }
+
+    pub fn redo(&mut self, src: &mut PixGrid, dst: &mut PixGrid) {
+        self.end_edit(src, dst);
+        if let Some(s) = self.redo.pop() {
+            self.undo.push(Snapshot::take(src, dst));
+            s.restore(src, dst);
+        }
+    }
+
+    pub fn can_undo(&self) -> bool { !self.undo.is_empty() }
+    pub fn can_redo(&self) -> bool { !self.redo.is_empty() }
+}
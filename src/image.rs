@@ -0,0 +1,355 @@
+/// how neighbourhood operations read pixels outside of the image
+#[derive(PartialEq, Clone, Copy, Default, serde::Deserialize, serde::Serialize)]
+pub enum BorderMode {
+    /// repeat the edge pixels
+    #[default]
+    Clamp,
+    /// use a fixed value
+    Constant,
+    /// mirror at the edge pixels (without repeating them)
+    Mirror,
+    /// continue at the opposite side, as if the image was periodic
+    Wrap,
+    /// don't process pixels whose neighbourhood leaves the image
+    Skip,
+}
+
+#[derive(PartialEq, Clone, Copy, Default, serde::Deserialize, serde::Serialize)]
+pub struct Border {
+    pub mode: BorderMode,
+    /// value outside of the image for `BorderMode::Constant`
+    pub constant: u8,
+}
+
+/// what a read at some position yields, see `ImageBuf::resolve_border`
+pub enum BorderTap {
+    /// the value of the pixel at that index
+    Pixel(u32, u32),
+    Constant(u8),
+    Skip,
+}
+
+/// map an index that may be outside of 0..len back into it by mirroring at the ends
+fn mirror_idx(i: i32, len: i32) -> i32 {
+    if len == 1 {
+        return 0;
+    }
+    let period = 2 * (len - 1);
+    let i = i.rem_euclid(period);
+    if i < len { i } else { period - i }
+}
+
+/// the types an image can store
+pub trait Pixel: Copy + PartialEq + Default {
+    fn to_f32(self) -> f32;
+    /// rounded and clamped to the range of the type
+    fn from_f32(v: f32) -> Self;
+}
+
+impl Pixel for u8 {
+    fn to_f32(self) -> f32 { self as f32 }
+    fn from_f32(v: f32) -> Self { v.round().clamp(0.0, u8::MAX as f32) as u8 }
+}
+
+impl Pixel for u16 {
+    fn to_f32(self) -> f32 { self as f32 }
+    fn from_f32(v: f32) -> Self { v.round().clamp(0.0, u16::MAX as f32) as u16 }
+}
+
+impl Pixel for f32 {
+    fn to_f32(self) -> f32 { self }
+    fn from_f32(v: f32) -> Self { v }
+}
+
+/// a single channel image with its pixels stored row after row.
+/// it is persisted as a list of rows, like the grids were stored before.
+#[derive(Clone, PartialEq, Default, serde::Deserialize, serde::Serialize)]
+#[serde(try_from = "Vec<Vec<T>>", into = "Vec<Vec<T>>")]
+#[serde(bound(serialize = "T: Pixel + serde::Serialize", deserialize = "T: Pixel + serde::Deserialize<'de>"))]
+pub struct ImageBuf<T> {
+    width: u32,
+    height: u32,
+    data: Vec<T>,
+}
+
+/// the stages of some operations store other values than pixels, like gradients or edge classes
+impl<T: Copy> ImageBuf<T> {
+    pub fn new(width: u32, height: u32, fill: T) -> Self {
+        Self { width, height, data: vec![fill; (width * height) as usize] }
+    }
+
+    /// f(x, y) for every pixel
+    pub fn from_fn(width: u32, height: u32, mut f: impl FnMut(u32, u32) -> T) -> Self {
+        let data = (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).map(|(x, y)| f(x, y)).collect();
+        Self { width, height, data }
+    }
+
+    /// an image from rows, which must all have the same length
+    pub fn from_rows(rows: Vec<Vec<T>>) -> Result<Self, String> {
+        let width = rows.first().map_or(0, |row| row.len());
+        if rows.iter().any(|row| row.len() != width) {
+            return Err("the rows of the image have different lengths".to_string());
+        }
+        let height = if width == 0 { 0 } else { rows.len() };
+        Ok(Self { width: width as u32, height: height as u32, data: rows.into_iter().flatten().collect() })
+    }
+
+    pub fn to_rows(&self) -> Vec<Vec<T>> {
+        self.rows().map(|row| row.to_vec()).collect()
+    }
+
+    pub fn width(&self) -> u32 { self.width }
+    pub fn height(&self) -> u32 { self.height }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn idx(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    pub fn in_bounds(&self, x: i32, y: i32) -> bool {
+        x >= 0 && (x as u32) < self.width && y >= 0 && (y as u32) < self.height
+    }
+
+    /// get the value without any tests
+    pub fn get(&self, x: u32, y: u32) -> T {
+        self.data[self.idx(x, y)]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, v: T) {
+        let i = self.idx(x, y);
+        self.data[i] = v;
+    }
+
+    /// get the value at the given position, clamped to (0, size).
+    /// in other words, the image is extended at the edges.
+    pub fn get_clamped(&self, x: i32, y: i32) -> T {
+        let x = x.clamp(0, self.width as i32 - 1);
+        let y = y.clamp(0, self.height as i32 - 1);
+        self.get(x as u32, y as u32)
+    }
+
+    /// find out where a read at the given position, which may be outside of the image, ends up.
+    /// this lets operations that work on intermediate images apply the same border handling.
+    pub fn resolve_border(&self, x: i32, y: i32, border: &Border) -> BorderTap {
+        if self.in_bounds(x, y) {
+            return BorderTap::Pixel(x as u32, y as u32);
+        }
+        let (w, h) = (self.width as i32, self.height as i32);
+        match border.mode {
+            BorderMode::Clamp => BorderTap::Pixel(x.clamp(0, w - 1) as u32, y.clamp(0, h - 1) as u32),
+            BorderMode::Constant => BorderTap::Constant(border.constant),
+            BorderMode::Mirror => BorderTap::Pixel(mirror_idx(x, w) as u32, mirror_idx(y, h) as u32),
+            BorderMode::Wrap => BorderTap::Pixel(x.rem_euclid(w) as u32, y.rem_euclid(h) as u32),
+            BorderMode::Skip => BorderTap::Skip,
+        }
+    }
+
+    pub fn row(&self, y: u32) -> &[T] {
+        let start = self.idx(0, y);
+        &self.data[start..start + self.width as usize]
+    }
+
+    pub fn row_mut(&mut self, y: u32) -> &mut [T] {
+        let start = self.idx(0, y);
+        let width = self.width as usize;
+        &mut self.data[start..start + width]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> {
+        // chunks_exact doesn't accept 0, an empty image has no rows anyway
+        self.data.chunks_exact(self.width.max(1) as usize)
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [T]> {
+        self.data.chunks_exact_mut(self.width.max(1) as usize)
+    }
+
+    /// the values of a column, from top to bottom
+    pub fn column(&self, x: u32) -> impl Iterator<Item = T> + '_ {
+        self.data.iter().skip(x as usize).step_by(self.width.max(1) as usize).copied()
+    }
+
+    pub fn columns(&self) -> impl Iterator<Item = impl Iterator<Item = T> + '_> {
+        (0..self.width).map(|x| self.column(x))
+    }
+
+    /// all the values, row after row
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        self.data.iter().copied()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.data.iter_mut()
+    }
+
+    /// all the values with their position, row after row
+    pub fn enumerate(&self) -> impl Iterator<Item = (u32, u32, T)> + '_ {
+        self.data.iter().enumerate().map(|(i, &v)| (i as u32 % self.width, i as u32 / self.width, v))
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+
+    /// an image of the same size with f(v) for every value v
+    pub fn map<U>(&self, f: impl Fn(T) -> U) -> ImageBuf<U> {
+        ImageBuf { width: self.width, height: self.height, data: self.data.iter().map(|&v| f(v)).collect() }
+    }
+}
+
+/// the operations that need to convert values
+impl<T: Pixel> ImageBuf<T> {
+    /// get the value at the given position, which may be outside of the image.
+    /// returns None if the border mode says this position must not be used.
+    pub fn get_border(&self, x: i32, y: i32, border: &Border) -> Option<T> {
+        match self.resolve_border(x, y, border) {
+            BorderTap::Pixel(x, y) => Some(self.get(x, y)),
+            BorderTap::Constant(color) => Some(T::from_f32(color as f32)),
+            BorderTap::Skip => None,
+        }
+    }
+
+    /// the image with another pixel type, rounding and clamping if it is smaller
+    pub fn convert<U: Pixel>(&self) -> ImageBuf<U> {
+        self.map(|v| U::from_f32(v.to_f32()))
+    }
+}
+
+impl<T: Pixel> TryFrom<Vec<Vec<T>>> for ImageBuf<T> {
+    type Error = String;
+
+    fn try_from(rows: Vec<Vec<T>>) -> Result<Self, String> {
+        Self::from_rows(rows)
+    }
+}
+
+impl<T: Pixel> From<ImageBuf<T>> for Vec<Vec<T>> {
+    fn from(img: ImageBuf<T>) -> Self {
+        img.to_rows()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// an image whose values tell where they come from
+    fn positions(width: u32, height: u32) -> ImageBuf<f32> {
+        ImageBuf::from_fn(width, height, |x, y| (x + 10 * y) as f32)
+    }
+
+    fn border(mode: BorderMode) -> Border {
+        Border { mode, constant: 7 }
+    }
+
+    #[test]
+    fn rows_round_trip() {
+        let rows = vec![vec![1u8, 2, 3], vec![4, 5, 6]];
+        let img = ImageBuf::from_rows(rows.clone()).unwrap();
+        assert_eq!((img.width(), img.height()), (3, 2));
+        assert_eq!(img.get(2, 1), 6);
+        assert_eq!(img.rows().collect::<Vec<_>>(), vec![&[1, 2, 3][..], &[4, 5, 6][..]]);
+        assert_eq!(img.to_rows(), rows);
+    }
+
+    #[test]
+    fn from_rows_rejects_ragged_rows() {
+        assert!(ImageBuf::from_rows(vec![vec![1u8, 2], vec![3]]).is_err());
+    }
+
+    #[test]
+    fn empty_image() {
+        let img = ImageBuf::<u8>::from_rows(vec![vec![], vec![]]).unwrap();
+        assert!(img.is_empty());
+        assert_eq!((img.width(), img.height()), (0, 0));
+        assert_eq!(img.rows().count(), 0);
+        assert_eq!(img.enumerate().count(), 0);
+    }
+
+    #[test]
+    fn column_and_enumerate() {
+        let img = positions(3, 2);
+        assert_eq!(img.column(1).collect::<Vec<_>>(), vec![1.0, 11.0]);
+        assert_eq!(img.columns().count(), 3);
+        let all: Vec<_> = img.enumerate().collect();
+        assert_eq!(all.len(), 6);
+        assert!(all.iter().all(|&(x, y, v)| v == (x + 10 * y) as f32));
+        assert_eq!(all[3], (0, 1, 10.0));
+    }
+
+    #[test]
+    fn border_inside_is_unchanged() {
+        let img = positions(4, 3);
+        for mode in [BorderMode::Clamp, BorderMode::Constant, BorderMode::Mirror, BorderMode::Wrap, BorderMode::Skip] {
+            assert_eq!(img.get_border(2, 1, &border(mode)), Some(12.0));
+        }
+    }
+
+    #[test]
+    fn border_clamp() {
+        let img = positions(4, 3);
+        let b = border(BorderMode::Clamp);
+        assert_eq!(img.get_border(-2, 1, &b), Some(10.0));
+        assert_eq!(img.get_border(5, 4, &b), Some(23.0));
+    }
+
+    #[test]
+    fn border_mirror() {
+        let img = positions(4, 3);
+        let b = border(BorderMode::Mirror);
+        // the edge pixel isn't repeated
+        assert_eq!(img.get_border(-1, 0, &b), Some(1.0));
+        assert_eq!(img.get_border(-3, 0, &b), Some(3.0));
+        assert_eq!(img.get_border(4, 0, &b), Some(2.0));
+        assert_eq!(img.get_border(0, -1, &b), Some(10.0));
+        assert_eq!(img.get_border(0, 3, &b), Some(10.0));
+        // further away than the image size
+        assert_eq!(img.get_border(-7, 0, &b), Some(1.0));
+    }
+
+    #[test]
+    fn border_wrap() {
+        let img = positions(4, 3);
+        let b = border(BorderMode::Wrap);
+        assert_eq!(img.get_border(-1, 0, &b), Some(3.0));
+        assert_eq!(img.get_border(4, 3, &b), Some(0.0));
+        assert_eq!(img.get_border(-9, -4, &b), Some(23.0));
+    }
+
+    #[test]
+    fn border_constant_and_skip() {
+        let img = positions(4, 3);
+        assert_eq!(img.get_border(-1, 0, &border(BorderMode::Constant)), Some(7.0));
+        assert_eq!(img.get_border(0, 3, &border(BorderMode::Skip)), None);
+    }
+
+    #[test]
+    fn border_single_pixel() {
+        let img = ImageBuf::new(1, 1, 5.0);
+        for mode in [BorderMode::Clamp, BorderMode::Mirror, BorderMode::Wrap] {
+            for (x, y) in [(-1, 0), (1, 0), (0, -3), (2, 2)] {
+                assert!(matches!(img.resolve_border(x, y, &border(mode)), BorderTap::Pixel(0, 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn border_single_row() {
+        let img = positions(3, 1);
+        assert!(matches!(img.resolve_border(1, -2, &border(BorderMode::Mirror)), BorderTap::Pixel(1, 0)));
+        assert!(matches!(img.resolve_border(-1, 1, &border(BorderMode::Wrap)), BorderTap::Pixel(2, 0)));
+        assert!(matches!(img.resolve_border(4, 5, &border(BorderMode::Clamp)), BorderTap::Pixel(2, 0)));
+    }
+
+    #[test]
+    fn convert_rounds_and_clamps() {
+        let img = ImageBuf::from_rows(vec![vec![-3.0, 0.4, 0.5, 1.6, 254.5, 300.0]]).unwrap();
+        assert_eq!(img.convert::<u8>().to_rows(), vec![vec![0, 0, 1, 2, 255, 255]]);
+        assert_eq!(img.convert::<u16>().to_rows(), vec![vec![0, 0, 1, 2, 255, 300]]);
+        let back = img.convert::<u8>().convert::<f32>();
+        assert_eq!(back.get(5, 0), 255.0);
+    }
+}
@@ -0,0 +1,211 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use eframe::egui;
+
+use crate::image::ImageBuf;
+use crate::pix_grid::MAX_SIZE;
+
+/// how an image is scaled down (or up) to the grid size
+#[derive(PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum Resampling {
+    /// take the source pixel under the center of the cell
+    Nearest,
+    /// average all source pixels covered by the cell
+    Box,
+    /// interpolate between the four source pixels around the center of the cell
+    Bilinear,
+}
+
+/// decode a png, jpeg or pgm/ppm image and convert it to rgb
+pub fn decode_rgb(bytes: &[u8]) -> Result<image::RgbImage, image::ImageError> {
+    Ok(image::load_from_memory(bytes)?.to_rgb8())
+}
+
+/// decode a png, jpeg or pgm/ppm image and convert it to grayscale
+pub fn decode_gray(bytes: &[u8]) -> Result<image::GrayImage, image::ImageError> {
+    Ok(image::load_from_memory(bytes)?.to_luma8())
+}
+
+/// resample an image of src_width x src_height, whose pixels are given by `get`, to width x height.
+pub fn resample(
+    src_width: u32, src_height: u32, get: impl Fn(u32, u32) -> f32,
+    width: u32, height: u32, mode: Resampling
+) -> ImageBuf<f32> {
+    // size of one grid cell in source pixels
+    let sx = src_width as f32 / width as f32;
+    let sy = src_height as f32 / height as f32;
+    let src = |x: i32, y: i32| -> f32 {
+        let x = x.clamp(0, src_width as i32 - 1) as u32;
+        let y = y.clamp(0, src_height as i32 - 1) as u32;
+        get(x, y)
+    };
+
+    ImageBuf::from_fn(width, height, |ix, iy| match mode {
+        Resampling::Nearest => {
+            src(((ix as f32 + 0.5) * sx) as i32, ((iy as f32 + 0.5) * sy) as i32)
+        },
+        Resampling::Box => {
+            // all pixels overlapping the cell. when upscaling that's just the one under it
+            let x0 = (ix as f32 * sx) as i32;
+            let y0 = (iy as f32 * sy) as i32;
+            let x1 = (((ix + 1) as f32 * sx).ceil() as i32).max(x0 + 1);
+            let y1 = (((iy + 1) as f32 * sy).ceil() as i32).max(y0 + 1);
+            let mut sum = 0.0;
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    sum += src(x, y);
+                }
+            }
+            sum / ((x1 - x0) * (y1 - y0)) as f32
+        },
+        Resampling::Bilinear => {
+            // position of the cell center in source pixel coordinates
+            let fx = (ix as f32 + 0.5) * sx - 0.5;
+            let fy = (iy as f32 + 0.5) * sy - 0.5;
+            let x0 = fx.floor();
+            let y0 = fy.floor();
+            let (wx, wy) = (fx - x0, fy - y0);
+            let (x0, y0) = (x0 as i32, y0 as i32);
+            let top = src(x0, y0) * (1.0 - wx) + src(x0 + 1, y0) * wx;
+            let bottom = src(x0, y0 + 1) * (1.0 - wx) + src(x0 + 1, y0 + 1) * wx;
+            top * (1.0 - wy) + bottom * wy
+        },
+    })
+}
+
+/// user settings for importing an image
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct ImportSettings {
+    pub width: u32,
+    pub height: u32,
+    /// derive the height from the width and the aspect ratio of the image
+    pub keep_aspect: bool,
+    pub resampling: Resampling,
+}
+
+impl Default for ImportSettings {
+    fn default() -> Self {
+        Self { width: 20, height: 12, keep_aspect: true, resampling: Resampling::Box }
+    }
+}
+
+impl ImportSettings {
+    /// decode the file and resample it according to the settings.
+    /// returns one plane per channel, three if `color` is set and one otherwise.
+    pub fn load(&self, bytes: &[u8], color: bool) -> Result<Vec<ImageBuf<f32>>, String> {
+        let img = decode(bytes, color)?;
+        let height = if self.keep_aspect {
+            let h = self.width as f32 * img.height() as f32 / img.width() as f32;
+            (h.round() as u32).clamp(1, MAX_SIZE)
+        } else {
+            self.height
+        };
+        Ok(planes(&img, self.width, height, self.resampling))
+    }
+}
+
+/// decode a png, jpeg or pgm/ppm image and convert it to rgb if `color` is set, to grayscale otherwise
+pub fn decode(bytes: &[u8], color: bool) -> Result<image::DynamicImage, String> {
+    if color {
+        decode_rgb(bytes).map(image::DynamicImage::ImageRgb8)
+    } else {
+        decode_gray(bytes).map(image::DynamicImage::ImageLuma8)
+    }.map_err(|e| e.to_string())
+}
+
+/// the channels of an image from `decode`, resampled to width x height
+pub fn planes(img: &image::DynamicImage, width: u32, height: u32, resampling: Resampling) -> Vec<ImageBuf<f32>> {
+    let samples = img.as_bytes();
+    let channels = img.color().channel_count() as usize;
+    let mut planes: Vec<ImageBuf<f32>> = (0..channels).map(|c| {
+        resample(
+            img.width(), img.height(),
+            |x, y| samples[((y * img.width() + x) as usize) * channels + c] as f32,
+            width, height, resampling)
+    }).collect();
+    // the image only has 8 bit values, so the grid gets them as well
+    planes.iter_mut().flat_map(|plane| plane.iter_mut()).for_each(|v| *v = v.round());
+    planes
+}
+
+const EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "pgm", "ppm"];
+
+/// lets the user pick a file with the given extensions.
+/// on the web, the dialog is async, so the contents arrive through a channel.
+pub struct FileLoader {
+    /// name of the file type in the dialog
+    name: &'static str,
+    extensions: &'static [&'static str],
+    tx: Sender<Result<Vec<u8>, String>>,
+    rx: Receiver<Result<Vec<u8>, String>>,
+}
+
+impl FileLoader {
+    pub fn new(name: &'static str, extensions: &'static [&'static str]) -> Self {
+        let (tx, rx) = channel();
+        Self { name, extensions, tx, rx }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open_dialog(&self, _ctx: &egui::Context) {
+        let file = rfd::FileDialog::new()
+            .add_filter(self.name, self.extensions)
+            .pick_file();
+        if let Some(path) = file {
+            let _ = self.tx.send(std::fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e)));
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn open_dialog(&self, ctx: &egui::Context) {
+        let tx = self.tx.clone();
+        let ctx = ctx.clone();
+        let dialog = rfd::AsyncFileDialog::new().add_filter(self.name, self.extensions);
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Some(file) = dialog.pick_file().await {
+                let _ = tx.send(Ok(file.read().await));
+                ctx.request_repaint();
+            }
+        });
+    }
+
+    /// returns the contents of a file that was picked since the last call
+    pub fn poll(&self) -> Option<Result<Vec<u8>, String>> {
+        // if several files arrived at once, the last one wins
+        self.rx.try_iter().last()
+    }
+}
+
+/// collects image files from the file dialog and from drag and drop
+pub struct ImageLoader {
+    files: FileLoader,
+}
+
+impl Default for ImageLoader {
+    fn default() -> Self {
+        Self { files: FileLoader::new("Images", &EXTENSIONS) }
+    }
+}
+
+impl ImageLoader {
+    pub fn open_dialog(&self, ctx: &egui::Context) {
+        self.files.open_dialog(ctx);
+    }
+
+    /// returns the contents of a file that was picked or dropped onto the window since the last call
+    pub fn poll(&self, ctx: &egui::Context) -> Option<Result<Vec<u8>, String>> {
+        for file in ctx.input(|i| i.raw.dropped_files.clone()) {
+            // the web build gets the bytes directly, native builds only the path
+            let contents = if let Some(bytes) = file.bytes {
+                Ok(bytes.to_vec())
+            } else if let Some(path) = file.path {
+                std::fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))
+            } else {
+                continue;
+            };
+            let _ = self.files.tx.send(contents);
+        }
+        self.files.poll()
+    }
+}
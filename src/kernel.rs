@@ -0,0 +1,421 @@
+use std::f32::consts::PI;
+
+use crate::image::{Border, ImageBuf};
+
+/// how far a kernel may reach from its center in each direction, so at most 7x7
+pub const MAX_CONV_EXTENT: i32 = 3;
+
+#[derive(PartialEq, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Convolution {
+    pub zero_centered: bool,
+    pub left: i32,
+    pub right: i32,
+    pub up: i32,
+    pub down: i32,
+    pub mask: Vec<Vec<f32>>,
+}
+
+impl Convolution {
+    /// kernel from the given rows. left and up are the offsets of the first column and row,
+    /// so a centered 3x3 kernel has -1, -1.
+    fn from_rows(zero_centered: bool, left: i32, up: i32, rows: &[&[f32]]) -> Self {
+        Self {
+            zero_centered,
+            left,
+            right: left + rows[0].len() as i32 - 1,
+            up,
+            down: up + rows.len() as i32 - 1,
+            mask: rows.iter().map(|row| row.to_vec()).collect(),
+        }
+    }
+
+    /// centered (2 * radius + 1)² kernel with the weights given by f(x, y)
+    fn from_fn(zero_centered: bool, radius: i32, f: impl Fn(f32, f32) -> f32) -> Self {
+        Self {
+            zero_centered,
+            left: -radius,
+            right: radius,
+            up: -radius,
+            down: radius,
+            mask: (-radius..=radius).map(|y| {
+                (-radius..=radius).map(|x| f(x as f32, y as f32)).collect()
+            }).collect(),
+        }
+    }
+
+    /// kernel from a text file with one row of weights per line, separated by whitespace.
+    /// weights may be written as fractions like 1/16. the kernel is centered, even sizes reach
+    /// one further to the right and down. a line `zero-centered` shifts the result by 127,
+    /// lines starting with # are comments.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut zero_centered = false;
+        let mut rows: Vec<Vec<f32>> = Vec::new();
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            if line == "zero-centered" {
+                zero_centered = true;
+                continue;
+            }
+            let row = line.split_whitespace().map(parse_weight).collect::<Result<Vec<f32>, String>>()?;
+            rows.push(row);
+        }
+        let width = rows.first().map_or(0, |row| row.len()) as i32;
+        let height = rows.len() as i32;
+        if width == 0 || rows.iter().any(|row| row.len() as i32 != width) {
+            return Err("the kernel needs rows of the same, nonzero length".to_string());
+        }
+        let (left, up) = (-(width - 1) / 2, -(height - 1) / 2);
+        if -left > MAX_CONV_EXTENT || left + width - 1 > MAX_CONV_EXTENT || -up > MAX_CONV_EXTENT || up + height - 1 > MAX_CONV_EXTENT {
+            return Err(format!("the kernel may be at most {0}x{0}", 2 * MAX_CONV_EXTENT + 1));
+        }
+        let rows: Vec<&[f32]> = rows.iter().map(|row| row.as_slice()).collect();
+        Ok(Self::from_rows(zero_centered, left, up, &rows))
+    }
+
+    /// scale the weights so they sum up to 1, which keeps the overall brightness
+    fn normalized(mut self) -> Self {
+        let sum: f32 = self.mask.iter().flatten().sum();
+        self.mask.iter_mut().flatten().for_each(|v| *v /= sum);
+        self
+    }
+
+    /// shift the weights so they sum up to 0, so flat areas give no response
+    fn zero_sum(mut self) -> Self {
+        let n = self.mask.iter().flatten().count() as f32;
+        let mean = self.mask.iter().flatten().sum::<f32>() / n;
+        self.mask.iter_mut().flatten().for_each(|v| *v -= mean);
+        self
+    }
+
+    /// change the kernel footprint. coefficients that are still inside it keep their
+    /// position relative to the center, new ones are 0.
+    pub fn set_extents(&mut self, left: i32, right: i32, up: i32, down: i32) {
+        let mask = (up..=down).map(|y| {
+            (left..=right).map(|x| {
+                if (self.left..=self.right).contains(&x) && (self.up..=self.down).contains(&y) {
+                    self.mask[(y - self.up) as usize][(x - self.left) as usize]
+                } else {
+                    0.0
+                }
+            }).collect()
+        }).collect();
+        self.left = left;
+        self.right = right;
+        self.up = up;
+        self.down = down;
+        self.mask = mask;
+    }
+
+    /// the weighted sum of the neighbourhood of x, y, before it's shifted by `zero_centered`.
+    /// None if the pixel is skipped because of the border mode.
+    pub fn sum_at(&self, img: &ImageBuf<f32>, x: i32, y: i32, border: &Border) -> Option<f32> {
+        let mut sum = 0.0;
+        for y_offset in self.up..=self.down {
+            for x_offset in self.left..=self.right {
+                let weight = self.mask[(y_offset - self.up) as usize][(x_offset - self.left) as usize];
+                sum += weight * img.get_border(x + x_offset, y + y_offset, border)?;
+            }
+        }
+        Some(sum)
+    }
+
+    /// the result of the convolution at x, y
+    pub fn apply_at(&self, img: &ImageBuf<f32>, x: i32, y: i32, border: &Border) -> Option<f32> {
+        let sum = self.sum_at(img, x, y, border)?;
+        Some(if self.zero_centered { sum + 127.0 } else { sum })
+    }
+}
+
+/// a number like 0.25 or a fraction like 1/4
+fn parse_weight(s: &str) -> Result<f32, String> {
+    let number = |s: &str| s.parse::<f32>().map_err(|_| format!("invalid weight: {}", s));
+    match s.split_once('/') {
+        Some((num, den)) => Ok(number(num)? / number(den)?),
+        None => number(s),
+    }
+}
+
+/// parameters of the generated presets
+#[derive(PartialEq, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct PresetParams {
+    pub radius: i32,
+    pub sigma: f32,
+    /// strength of unsharp masking
+    pub amount: f32,
+    /// direction of motion blur in degrees, counterclockwise from the x axis
+    pub angle: f32,
+}
+
+impl Default for PresetParams {
+    fn default() -> Self {
+        Self { radius: 1, sigma: 1.0, amount: 1.0, angle: 0.0 }
+    }
+}
+
+impl PresetParams {
+    /// bring loaded parameters into the ranges of the sliders, NaN becomes the lower end
+    pub fn clamp(&mut self) {
+        let clamp = |v: f32, min: f32, max: f32| if v.is_nan() { min } else { v.clamp(min, max) };
+        self.radius = self.radius.clamp(0, MAX_CONV_EXTENT);
+        self.sigma = clamp(self.sigma, 0.2, 4.0);
+        self.amount = clamp(self.amount, 0.0, 4.0);
+        self.angle = clamp(self.angle, 0.0, 180.0);
+    }
+}
+
+#[derive(PartialEq)]
+pub enum Param {
+    Radius,
+    Sigma,
+    Amount,
+    Angle,
+}
+
+pub struct Preset {
+    pub name: &'static str,
+    /// short name for the command line
+    pub id: &'static str,
+    /// the parameters the preset depends on
+    pub params: &'static [Param],
+    pub build: fn(&PresetParams) -> Convolution,
+}
+
+fn gaussian(sigma: f32) -> impl Fn(f32, f32) -> f32 {
+    move |x, y| (-(x * x + y * y) / (2.0 * sigma * sigma)).exp()
+}
+
+/// normalized gaussian blur of the given radius
+pub fn gaussian_blur(radius: i32, sigma: f32) -> Convolution {
+    Convolution::from_fn(false, radius, gaussian(sigma)).normalized()
+}
+
+pub fn prewitt_x() -> Convolution {
+    Convolution::from_rows(true, -1, -1, &[
+        &[-1.0, 0.0, 1.0],
+        &[-1.0, 0.0, 1.0],
+        &[-1.0, 0.0, 1.0],
+    ])
+}
+
+pub fn prewitt_y() -> Convolution {
+    Convolution::from_rows(true, -1, -1, &[
+        &[1.0, 1.0, 1.0],
+        &[0.0, 0.0, 0.0],
+        &[-1.0, -1.0, -1.0],
+    ])
+}
+
+pub fn sobel_x() -> Convolution {
+    Convolution::from_rows(true, -1, -1, &[
+        &[-1.0, 0.0, 1.0],
+        &[-2.0, 0.0, 2.0],
+        &[-1.0, 0.0, 1.0],
+    ])
+}
+
+pub fn sobel_y() -> Convolution {
+    Convolution::from_rows(true, -1, -1, &[
+        &[1.0, 2.0, 1.0],
+        &[0.0, 0.0, 0.0],
+        &[-1.0, -2.0, -1.0],
+    ])
+}
+
+pub fn scharr_x() -> Convolution {
+    Convolution::from_rows(true, -1, -1, &[
+        &[-3.0, 0.0, 3.0],
+        &[-10.0, 0.0, 10.0],
+        &[-3.0, 0.0, 3.0],
+    ])
+}
+
+pub fn scharr_y() -> Convolution {
+    Convolution::from_rows(true, -1, -1, &[
+        &[3.0, 10.0, 3.0],
+        &[0.0, 0.0, 0.0],
+        &[-3.0, -10.0, -3.0],
+    ])
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset { name: "Binomial", id: "binomial", params: &[], build: |_| Convolution::from_rows(false, -1, -1, &[
+        &[1.0/16.0, 2.0/16.0, 1.0/16.0],
+        &[2.0/16.0, 4.0/16.0, 2.0/16.0],
+        &[1.0/16.0, 2.0/16.0, 1.0/16.0],
+    ])},
+    Preset { name: "Box blur", id: "box", params: &[Param::Radius], build: |p| {
+        Convolution::from_fn(false, p.radius, |_, _| 1.0).normalized()
+    }},
+    Preset { name: "Gaussian", id: "gaussian", params: &[Param::Radius, Param::Sigma], build: |p| gaussian_blur(p.radius, p.sigma) },
+    Preset { name: "Laplacian (4-neighbour)", id: "laplacian4", params: &[], build: |_| Convolution::from_rows(true, -1, -1, &[
+        &[0.0, 1.0, 0.0],
+        &[1.0, -4.0, 1.0],
+        &[0.0, 1.0, 0.0],
+    ])},
+    Preset { name: "Laplacian (8-neighbour)", id: "laplacian8", params: &[], build: |_| Convolution::from_rows(true, -1, -1, &[
+        &[1.0, 1.0, 1.0],
+        &[1.0, -8.0, 1.0],
+        &[1.0, 1.0, 1.0],
+    ])},
+    Preset { name: "Laplacian of Gaussian", id: "log", params: &[Param::Radius, Param::Sigma], build: |p| {
+        let s2 = p.sigma * p.sigma;
+        Convolution::from_fn(true, p.radius, |x, y| {
+            let r2 = (x * x + y * y) / (2.0 * s2);
+            -1.0 / (PI * s2 * s2) * (1.0 - r2) * (-r2).exp()
+        }).zero_sum()
+    }},
+    Preset { name: "Vertical Prewitt", id: "prewitt-x", params: &[], build: |_| prewitt_x() },
+    Preset { name: "Horizontal Prewitt", id: "prewitt-y", params: &[], build: |_| prewitt_y() },
+    Preset { name: "Vertical Sobel", id: "sobel-x", params: &[], build: |_| sobel_x() },
+    Preset { name: "Horizontal Sobel", id: "sobel-y", params: &[], build: |_| sobel_y() },
+    Preset { name: "Vertical Scharr", id: "scharr-x", params: &[], build: |_| scharr_x() },
+    Preset { name: "Horizontal Scharr", id: "scharr-y", params: &[], build: |_| scharr_y() },
+    // the roberts kernels are 2x2, so they reach right and down from the center
+    Preset { name: "Roberts cross (↘)", id: "roberts1", params: &[], build: |_| Convolution::from_rows(true, 0, 0, &[
+        &[1.0, 0.0],
+        &[0.0, -1.0],
+    ])},
+    Preset { name: "Roberts cross (↙)", id: "roberts2", params: &[], build: |_| Convolution::from_rows(true, 0, 0, &[
+        &[0.0, 1.0],
+        &[-1.0, 0.0],
+    ])},
+    Preset { name: "Sharpen", id: "sharpen", params: &[], build: |_| Convolution::from_rows(false, -1, -1, &[
+        &[0.0, -1.0, 0.0],
+        &[-1.0, 5.0, -1.0],
+        &[0.0, -1.0, 0.0],
+    ])},
+    Preset { name: "Unsharp mask", id: "unsharp", params: &[Param::Radius, Param::Sigma, Param::Amount], build: |p| {
+        // original + amount * (original - blurred)
+        let mut conv = Convolution::from_fn(false, p.radius, gaussian(p.sigma)).normalized();
+        conv.mask.iter_mut().flatten().for_each(|v| *v *= -p.amount);
+        conv.mask[p.radius as usize][p.radius as usize] += 1.0 + p.amount;
+        conv
+    }},
+    Preset { name: "Emboss", id: "emboss", params: &[], build: |_| Convolution::from_rows(true, -1, -1, &[
+        &[-1.0, -1.0, 0.0],
+        &[-1.0, 0.0, 1.0],
+        &[0.0, 1.0, 1.0],
+    ])},
+    Preset { name: "Motion blur", id: "motion-blur", params: &[Param::Radius, Param::Angle], build: |p| {
+        // weight the cells that a line through the center in the given direction passes
+        let (dy, dx) = (-p.angle.to_radians()).sin_cos();
+        Convolution::from_fn(false, p.radius, |x, y| {
+            let dist_to_line = (x * dy - y * dx).abs();
+            if dist_to_line <= 0.5 { 1.0 } else { 0.0 }
+        }).normalized()
+    }},
+];
+
+#[derive(PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum MaskShape {
+    Cross,
+    Square,
+    Disk,
+    /// a line through the center
+    Line,
+}
+
+/// a square neighbourhood of (2 * radius + 1)² pixels, of which only some are used
+#[derive(PartialEq, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Mask {
+    pub radius: i32,
+    pub mask: Vec<Vec<bool>>,
+}
+
+impl Mask {
+    /// mask of the given shape. angle is the direction of `MaskShape::Line` in degrees,
+    /// counterclockwise from the x axis.
+    pub fn from_shape(shape: MaskShape, radius: i32, angle: f32) -> Self {
+        let (dy, dx) = (-angle.to_radians()).sin_cos();
+        let inside = |x: i32, y: i32| match shape {
+            MaskShape::Cross => x == 0 || y == 0,
+            MaskShape::Square => true,
+            // the extra half pixel keeps small disks from degenerating into crosses
+            MaskShape::Disk => ((x * x + y * y) as f32).sqrt() <= radius as f32 + 0.5,
+            MaskShape::Line => (x as f32 * dy - y as f32 * dx).abs() <= 0.5,
+        };
+        Self {
+            radius,
+            mask: (-radius..=radius).map(|y| (-radius..=radius).map(|x| inside(x, y)).collect()).collect(),
+        }
+    }
+
+    pub fn get(&self, x_off: i32, y_off: i32) -> bool {
+        self.mask[(y_off + self.radius) as usize][(x_off + self.radius) as usize]
+    }
+
+    /// the offsets from the center of all the used pixels
+    pub fn offsets(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        let r = self.radius;
+        (-r..=r).flat_map(move |y| (-r..=r).map(move |x| (x, y)))
+            .filter(|&(x, y)| self.get(x, y))
+    }
+
+    /// grow or shrink the mask around its center. new pixels are unused.
+    pub fn set_radius(&mut self, radius: i32) {
+        let mask = (-radius..=radius).map(|y| {
+            (-radius..=radius).map(|x| x.abs() <= self.radius && y.abs() <= self.radius && self.get(x, y)).collect()
+        }).collect();
+        self.radius = radius;
+        self.mask = mask;
+    }
+
+    pub fn is_consistent(&self) -> bool {
+        let size = (2 * self.radius + 1) as usize;
+        self.radius >= 0 && self.mask.len() == size && self.mask.iter().all(|row| row.len() == size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::BorderMode;
+
+    fn border(mode: BorderMode) -> Border {
+        Border { mode, constant: 0 }
+    }
+
+    #[test]
+    fn parse_centers_the_kernel() {
+        let conv = Convolution::parse("# a comment\n1 2 1\n\n2 4 2\n1 2 1\n").unwrap();
+        assert!(!conv.zero_centered);
+        assert_eq!((conv.left, conv.right, conv.up, conv.down), (-1, 1, -1, 1));
+        assert_eq!(conv.mask[1], vec![2.0, 4.0, 2.0]);
+    }
+
+    #[test]
+    fn parse_even_sizes_and_fractions() {
+        let conv = Convolution::parse("zero-centered\n1/4 -1/4\n0.5 -0.5").unwrap();
+        assert!(conv.zero_centered);
+        assert_eq!((conv.left, conv.right, conv.up, conv.down), (0, 1, 0, 1));
+        assert_eq!(conv.mask, vec![vec![0.25, -0.25], vec![0.5, -0.5]]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Convolution::parse("").is_err());
+        assert!(Convolution::parse("1 2\n3").is_err());
+        assert!(Convolution::parse("1 x").is_err());
+        assert!(Convolution::parse(&"1 ".repeat(9)).is_err());
+    }
+
+
+    #[test]
+    fn apply_at() {
+        let img = ImageBuf::from_rows(vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]]).unwrap();
+        let conv = Convolution::from_rows(false, -1, 0, &[&[1.0, 1.0, 1.0]]);
+        assert_eq!(conv.apply_at(&img, 1, 0, &border(BorderMode::Clamp)), Some(6.0));
+        assert_eq!(conv.apply_at(&img, 0, 1, &border(BorderMode::Clamp)), Some(13.0));
+        assert_eq!(conv.apply_at(&img, 0, 1, &border(BorderMode::Constant)), Some(9.0));
+        assert_eq!(conv.apply_at(&img, 0, 1, &border(BorderMode::Skip)), None);
+        assert_eq!(conv.apply_at(&img, 1, 1, &border(BorderMode::Skip)), Some(15.0));
+    }
+
+    #[test]
+    fn apply_at_zero_centered() {
+        let img = ImageBuf::new(3, 3, 10.0);
+        let conv = Convolution::from_rows(true, -1, 0, &[&[-1.0, 0.0, 1.0]]);
+        assert_eq!(conv.apply_at(&img, 1, 1, &border(BorderMode::Clamp)), Some(127.0));
+        assert_eq!(conv.sum_at(&img, 1, 1, &border(BorderMode::Clamp)), Some(0.0));
+    }
+}
@@ -1,5 +1,23 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
 mod app;
+pub mod batch;
+mod canny;
+mod color;
+mod export;
+mod fourier;
+mod gradient;
+mod graph;
+mod graph_editor;
+pub mod grid_view;
+mod histogram;
+mod history;
+pub mod image;
+mod import;
+mod kernel;
+mod lut;
+mod morphology;
+mod pipeline;
 pub mod pix_grid;
+mod threshold;
 pub use app::ImgProcDemo;
@@ -0,0 +1,218 @@
+use eframe::egui;
+use egui::{Color32, Pos2, Rounding, Sense, Stroke};
+
+#[derive(PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum LutPreset {
+    Identity,
+    Invert,
+    Gamma,
+    Log,
+    BrightnessContrast,
+    Posterize,
+    BitPlane,
+}
+
+impl LutPreset {
+    pub const ALL: [LutPreset; 7] = [
+        LutPreset::Identity, LutPreset::Invert, LutPreset::Gamma, LutPreset::Log,
+        LutPreset::BrightnessContrast, LutPreset::Posterize, LutPreset::BitPlane,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LutPreset::Identity => "Identity",
+            LutPreset::Invert => "Invert",
+            LutPreset::Gamma => "Gamma",
+            LutPreset::Log => "Log",
+            LutPreset::BrightnessContrast => "Brightness/contrast",
+            LutPreset::Posterize => "Posterize",
+            LutPreset::BitPlane => "Bit plane",
+        }
+    }
+}
+
+/// a point operation, which maps every value through a lookup table
+#[derive(PartialEq, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct PointOp {
+    /// always 256 entries
+    pub lut: Vec<u8>,
+    /// control points of the curve editor, sorted by input value. the first one is
+    /// always at input 0, the last one at 255.
+    pub points: Vec<(f32, f32)>,
+    pub preset: LutPreset,
+    pub gamma: f32,
+    pub brightness: f32,
+    pub contrast: f32,
+    pub levels: u32,
+    pub bit: u32,
+    // index of the control point being dragged
+    #[serde(skip)]
+    dragged: Option<usize>,
+}
+
+impl Default for PointOp {
+    fn default() -> Self {
+        let mut s = Self {
+            lut: Vec::new(),
+            points: Vec::new(),
+            preset: LutPreset::Invert,
+            gamma: 0.5,
+            brightness: 0.0,
+            contrast: 1.0,
+            levels: 4,
+            bit: 7,
+            dragged: None,
+        };
+        s.apply_preset();
+        s
+    }
+}
+
+/// how many control points a preset curve gets in the editor
+const PRESET_POINTS: usize = 9;
+
+impl PointOp {
+    pub fn map(&self, v: u8) -> u8 {
+        self.lut[v as usize]
+    }
+
+    pub fn is_consistent(&self) -> bool {
+        self.lut.len() == 256 && self.points.len() >= 2
+    }
+
+    /// fill the lookup table from the current preset and its parameters.
+    /// the control points follow the curve, but can't represent steps exactly.
+    pub fn apply_preset(&mut self) {
+        let f: Box<dyn Fn(f32) -> f32> = match self.preset {
+            LutPreset::Identity => Box::new(|v| v),
+            LutPreset::Invert => Box::new(|v| 255.0 - v),
+            LutPreset::Gamma => {
+                let gamma = self.gamma;
+                Box::new(move |v| 255.0 * (v / 255.0).powf(gamma))
+            },
+            LutPreset::Log => Box::new(|v| 255.0 * (1.0 + v).ln() / 256.0_f32.ln()),
+            LutPreset::BrightnessContrast => {
+                let (b, c) = (self.brightness, self.contrast);
+                Box::new(move |v| (v - 127.5) * c + 127.5 + b)
+            },
+            LutPreset::Posterize => {
+                let step = 255.0 / (self.levels.max(2) - 1) as f32;
+                Box::new(move |v| (v / step).round() * step)
+            },
+            LutPreset::BitPlane => {
+                let bit = self.bit;
+                Box::new(move |v| if (v as u8 >> bit) & 1 == 1 { 255.0 } else { 0.0 })
+            },
+        };
+        self.lut = (0..256).map(|v| f(v as f32).round().clamp(0.0, 255.0) as u8).collect();
+        self.points = (0..PRESET_POINTS).map(|i| {
+            let x = (i * 255 / (PRESET_POINTS - 1)) as f32;
+            (x, self.lut[x as usize] as f32)
+        }).collect();
+    }
+
+    /// fill the lookup table by linear interpolation between the control points
+    fn lut_from_points(&mut self) {
+        self.lut = (0..256).map(|v| {
+            let v = v as f32;
+            let i = self.points.iter().rposition(|p| p.0 <= v).unwrap_or(0).min(self.points.len() - 2);
+            let ((x0, y0), (x1, y1)) = (self.points[i], self.points[i + 1]);
+            let t = if x1 > x0 { ((v - x0) / (x1 - x0)).clamp(0.0, 1.0) } else { 0.0 };
+            (y0 + t * (y1 - y0)).round().clamp(0.0, 255.0) as u8
+        }).collect();
+    }
+
+    /// draw the curve with its control points. drag a point to move it, click to add one
+    /// and right click to remove one. returns whether the curve changed.
+    pub fn curve_editor(&mut self, ui: &mut egui::Ui, size: f32) -> bool {
+        let (rect, response) = ui.allocate_exact_size(egui::vec2(size, size), Sense::click_and_drag());
+        let to_screen = |(x, y): (f32, f32)| Pos2::new(
+            rect.min.x + x / 255.0 * rect.width(),
+            rect.max.y - y / 255.0 * rect.height());
+        let from_screen = |p: Pos2| (
+            ((p.x - rect.min.x) / rect.width() * 255.0).clamp(0.0, 255.0),
+            ((rect.max.y - p.y) / rect.height() * 255.0).clamp(0.0, 255.0));
+        let grab_radius = 8.0;
+        let point_at = |points: &[(f32, f32)], pos: Pos2| points.iter()
+            .position(|&p| to_screen(p).distance(pos) < grab_radius);
+
+        let mut changed = false;
+        if let Some(pos) = response.interact_pointer_pos() {
+            if response.drag_started() {
+                self.dragged = point_at(&self.points, pos);
+            }
+            if let (true, Some(i)) = (response.dragged(), self.dragged) {
+                let (mut x, y) = from_screen(pos);
+                // the end points stay at the ends, the others between their neighbours
+                let last = self.points.len() - 1;
+                x = match i {
+                    0 => 0.0,
+                    i if i == last => 255.0,
+                    // not clamp(), the neighbours may be closer than two apart
+                    i => x.max(self.points[i - 1].0 + 1.0).min(self.points[i + 1].0 - 1.0),
+                };
+                self.points[i] = (x, y);
+                changed = true;
+            }
+        }
+        if response.drag_released() {
+            self.dragged = None;
+        }
+        if let Some(pos) = response.interact_pointer_pos() {
+            if response.clicked() && point_at(&self.points, pos).is_none() {
+                let p = from_screen(pos);
+                let i = self.points.iter().position(|q| q.0 > p.0).unwrap_or(self.points.len() - 1);
+                self.points.insert(i.max(1), p);
+                changed = true;
+            }
+            if response.secondary_clicked() {
+                if let Some(i) = point_at(&self.points, pos) {
+                    if i != 0 && i != self.points.len() - 1 {
+                        self.points.remove(i);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        if changed {
+            self.lut_from_points();
+        }
+
+        if ui.is_rect_visible(rect) {
+            let painter = ui.painter();
+            painter.rect(rect, Rounding::ZERO, Color32::from_gray(20), Stroke::NONE);
+            painter.line_segment([rect.left_bottom(), rect.right_top()], Stroke::new(1.0_f32, Color32::from_gray(60)));
+            let curve = self.lut.iter().enumerate().map(|(v, &out)| to_screen((v as f32, out as f32))).collect();
+            painter.add(egui::Shape::line(curve, Stroke::new(1.5_f32, Color32::from_gray(220))));
+            for &p in &self.points {
+                painter.circle_filled(to_screen(p), 4.0, Color32::from_rgb(20, 200, 20));
+            }
+        }
+        changed
+    }
+
+    /// table of all input -> output pairs, with the given input highlighted
+    pub fn table(&self, ui: &mut egui::Ui, highlight: Option<u8>) {
+        egui::ScrollArea::vertical().id_source("lut_table").show(ui, |ui| {
+            egui::Grid::new("lut_grid").striped(true).show(ui, |ui| {
+                ui.strong("in");
+                ui.strong("out");
+                ui.end_row();
+                for (v, &out) in self.lut.iter().enumerate() {
+                    let text = |s: String| if highlight == Some(v as u8) {
+                        egui::RichText::new(s).strong().color(Color32::from_rgb(20, 200, 20))
+                    } else {
+                        egui::RichText::new(s)
+                    };
+                    ui.label(text(v.to_string()));
+                    let response = ui.label(text(out.to_string()));
+                    if highlight == Some(v as u8) {
+                        response.scroll_to_me(Some(egui::Align::Center));
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+    }
+}
@@ -0,0 +1,217 @@
+use crate::kernel::Mask;
+use crate::image::{Border, BorderTap, ImageBuf};
+
+/// grayscale morphology, working directly on the values instead of thresholding them
+#[derive(PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum MorphOp {
+    /// minimum over the structuring element
+    Erode,
+    /// maximum over the reflected structuring element
+    Dilate,
+    /// erosion followed by dilation, removes bright details smaller than the element
+    Open,
+    /// dilation followed by erosion, removes dark details smaller than the element
+    Close,
+    /// dilation minus erosion
+    Gradient,
+    /// image minus its opening, keeps only the bright details
+    WhiteTopHat,
+    /// closing minus the image, keeps only the dark details
+    BlackTopHat,
+}
+
+impl MorphOp {
+    pub const ALL: [MorphOp; 7] = [
+        MorphOp::Erode, MorphOp::Dilate, MorphOp::Open, MorphOp::Close,
+        MorphOp::Gradient, MorphOp::WhiteTopHat, MorphOp::BlackTopHat,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MorphOp::Erode => "Erosion",
+            MorphOp::Dilate => "Dilation",
+            MorphOp::Open => "Opening",
+            MorphOp::Close => "Closing",
+            MorphOp::Gradient => "Gradient",
+            MorphOp::WhiteTopHat => "White top-hat",
+            MorphOp::BlackTopHat => "Black top-hat",
+        }
+    }
+
+    /// offsets of all the source pixels the result of one pixel depends on
+    pub fn footprint(&self, se: &Mask) -> Vec<(i32, i32)> {
+        let offsets: Vec<(i32, i32)> = se.offsets().collect();
+        let reflected: Vec<(i32, i32)> = offsets.iter().map(|&(x, y)| (-x, -y)).collect();
+        // for the composite operations, the offsets of the second step are shifted by the first
+        let shifted = |sign: i32| -> Vec<(i32, i32)> {
+            let mut taps = Vec::new();
+            for &(x1, y1) in &offsets {
+                for &(x2, y2) in &offsets {
+                    taps.push((sign * (x2 - x1), sign * (y2 - y1)));
+                }
+            }
+            taps
+        };
+        let mut taps = match self {
+            MorphOp::Erode => offsets.clone(),
+            MorphOp::Dilate => reflected,
+            MorphOp::Gradient => [offsets.clone(), reflected].concat(),
+            MorphOp::Open => shifted(1),
+            MorphOp::Close => shifted(-1),
+            MorphOp::WhiteTopHat => [shifted(1), vec![(0, 0)]].concat(),
+            MorphOp::BlackTopHat => [shifted(-1), vec![(0, 0)]].concat(),
+        };
+        taps.sort_unstable();
+        taps.dedup();
+        taps
+    }
+}
+
+fn erode_at(get: &dyn Fn(i32, i32) -> Option<f32>, x: i32, y: i32, se: &Mask) -> Option<f32> {
+    se.offsets().map(|(dx, dy)| get(x + dx, y + dy)).try_fold(f32::INFINITY, |m, v| Some(m.min(v?)))
+}
+
+fn dilate_at(get: &dyn Fn(i32, i32) -> Option<f32>, x: i32, y: i32, se: &Mask) -> Option<f32> {
+    se.offsets().map(|(dx, dy)| get(x - dx, y - dy)).try_fold(f32::NEG_INFINITY, |m, v| Some(m.max(v?)))
+}
+
+/// read an intermediate image, whose pixels inside of the image are given by `at`,
+/// with the same border handling as the source image
+fn with_border(src: &ImageBuf<f32>, border: &Border, x: i32, y: i32, at: impl Fn(u32, u32) -> Option<f32>) -> Option<f32> {
+    match src.resolve_border(x, y, border) {
+        BorderTap::Pixel(x, y) => at(x, y),
+        BorderTap::Constant(color) => Some(color as f32),
+        BorderTap::Skip => None,
+    }
+}
+
+/// the result at x, y given the source, its erosion and its dilation, each with border handling
+fn combine(
+    op: MorphOp, x: i32, y: i32, se: &Mask,
+    orig: &dyn Fn(i32, i32) -> Option<f32>,
+    eroded: &dyn Fn(i32, i32) -> Option<f32>,
+    dilated: &dyn Fn(i32, i32) -> Option<f32>,
+) -> Option<f32> {
+    match op {
+        MorphOp::Erode => eroded(x, y),
+        MorphOp::Dilate => dilated(x, y),
+        MorphOp::Open => dilate_at(eroded, x, y, se),
+        MorphOp::Close => erode_at(dilated, x, y, se),
+        // the differences can only become negative for elements without their center
+        MorphOp::Gradient => Some(dilated(x, y)? - eroded(x, y)?),
+        MorphOp::WhiteTopHat => Some(orig(x, y)? - dilate_at(eroded, x, y, se)?),
+        MorphOp::BlackTopHat => Some(erode_at(dilated, x, y, se)? - orig(x, y)?),
+    }
+}
+
+/// the largest threshold of the boolean operation. values above it are true, so with 255 nothing would be.
+pub const MAX_THRESHOLD: u8 = 254;
+
+/// binary erosion or dilation of the 3x3 neighbourhood given by `mask`.
+/// values above the threshold count as true, the result is 0 or 255.
+pub fn boolean_at(
+    img: &ImageBuf<f32>, x: i32, y: i32,
+    mask: &[[bool; 3]; 3], dilation: bool, threshold: f32, border: &Border,
+) -> Option<f32> {
+    // for erosion, we start with true and only stay true if all the values are
+    // true, for dilation, we start with false and go true if any of the values is
+    // true
+    let mut b = !dilation;
+    for y_off in -1..=1 {
+        for x_off in -1..=1 {
+            if mask[(y_off + 1) as usize][(x_off + 1) as usize] {
+                let s_val = img.get_border(x + x_off, y + y_off, border)?;
+                if dilation {
+                    b |= s_val > threshold;
+                } else {
+                    b &= s_val > threshold;
+                }
+            }
+        }
+    }
+    Some(if b {255.0} else {0.0})
+}
+
+/// sort the values under the mask and pick the one at the percentile,
+/// 0 is the minimum and 100 the maximum
+pub fn rank_at(img: &ImageBuf<f32>, x: i32, y: i32, mask: &Mask, percentile: f32, border: &Border) -> Option<f32> {
+    let mut values = mask.offsets()
+        .map(|(dx, dy)| img.get_border(x + dx, y + dy, border))
+        .collect::<Option<Vec<f32>>>()?;
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable_by(f32::total_cmp);
+    let idx = (percentile / 100.0 * (values.len() - 1) as f32).round() as usize;
+    Some(values[idx])
+}
+
+/// the result for a single pixel. None if it is skipped because of the border mode.
+pub fn apply_at(op: MorphOp, src: &ImageBuf<f32>, x: i32, y: i32, se: &Mask, border: &Border) -> Option<f32> {
+    let orig = |x, y| src.get_border(x, y, border);
+    let eroded = |x, y| with_border(src, border, x, y, |x, y| erode_at(&orig, x as i32, y as i32, se));
+    let dilated = |x, y| with_border(src, border, x, y, |x, y| dilate_at(&orig, x as i32, y as i32, se));
+    combine(op, x, y, se, &orig, &eroded, &dilated)
+}
+
+/// the result for the whole image, as rows. this gives the same values as `apply_at`,
+/// but computes the intermediate images only once.
+pub fn apply_whole(op: MorphOp, src: &ImageBuf<f32>, se: &Mask, border: &Border) -> Vec<Vec<Option<f32>>> {
+    let orig = |x, y| src.get_border(x, y, border);
+    let whole = |f: &dyn Fn(i32, i32) -> Option<f32>| -> Vec<Vec<Option<f32>>> {
+        (0..src.height() as i32).map(|y| (0..src.width() as i32).map(|x| f(x, y)).collect()).collect()
+    };
+    let eroded_img = whole(&|x, y| erode_at(&orig, x, y, se));
+    let dilated_img = whole(&|x, y| dilate_at(&orig, x, y, se));
+    let eroded = |x, y| with_border(src, border, x, y, |x, y| eroded_img[y as usize][x as usize]);
+    let dilated = |x, y| with_border(src, border, x, y, |x, y| dilated_img[y as usize][x as usize]);
+    whole(&|x, y| combine(op, x, y, se, &orig, &eroded, &dilated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::BorderMode;
+
+    const CROSS: [[bool; 3]; 3] = [[false, true, false], [true, true, true], [false, true, false]];
+
+    /// a single bright pixel in the middle of a dark image
+    fn dot() -> ImageBuf<f32> {
+        ImageBuf::from_fn(5, 5, |x, y| if (x, y) == (2, 2) { 200.0 } else { 10.0 })
+    }
+
+    #[test]
+    fn dilation_grows_the_dot() {
+        let img = dot();
+        let border = Border::default();
+        assert_eq!(boolean_at(&img, 2, 1, &CROSS, true, 127.0, &border), Some(255.0));
+        assert_eq!(boolean_at(&img, 1, 1, &CROSS, true, 127.0, &border), Some(0.0));
+    }
+
+    #[test]
+    fn erosion_removes_the_dot() {
+        let img = dot();
+        let border = Border::default();
+        assert_eq!(boolean_at(&img, 2, 2, &CROSS, false, 127.0, &border), Some(0.0));
+        let bright = ImageBuf::new(5, 5, 200.0);
+        assert_eq!(boolean_at(&bright, 0, 0, &CROSS, false, 127.0, &border), Some(255.0));
+    }
+
+    #[test]
+    fn threshold_is_exclusive() {
+        let img = ImageBuf::new(3, 3, 127.0);
+        let border = Border::default();
+        assert_eq!(boolean_at(&img, 1, 1, &CROSS, true, 127.0, &border), Some(0.0));
+        assert_eq!(boolean_at(&img, 1, 1, &CROSS, true, 126.0, &border), Some(255.0));
+    }
+
+    #[test]
+    fn border_modes() {
+        let img = ImageBuf::new(3, 3, 200.0);
+        let constant = Border { mode: BorderMode::Constant, constant: 0 };
+        assert_eq!(boolean_at(&img, 0, 1, &CROSS, false, 127.0, &constant), Some(0.0));
+        let skip = Border { mode: BorderMode::Skip, constant: 0 };
+        assert_eq!(boolean_at(&img, 0, 1, &CROSS, false, 127.0, &skip), None);
+        assert_eq!(boolean_at(&img, 1, 1, &CROSS, false, 127.0, &skip), Some(255.0));
+    }
+}
@@ -0,0 +1,178 @@
+use crate::app::{Tool, ToolVars};
+use crate::grid_view::GridView;
+use crate::pix_grid::{Display, PixGrid};
+
+/// a tool with its own parameters, as one step of a pipeline
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct Step {
+    pub tool: Tool,
+    pub vars: ToolVars,
+    /// disabled steps pass their input on unchanged
+    pub enabled: bool,
+}
+
+impl Step {
+    /// a frequency step whose mask was painted on another size than its input, see `ToolVars::filter_fits`
+    pub fn uses_preset_mask(&self, width: u32, height: u32) -> bool {
+        self.enabled && self.tool == Tool::Frequency && !self.vars.filter_fits(width, height)
+    }
+}
+
+/// an ordered list of steps, each applied to the whole result of the one before,
+/// starting with the source grid. the intermediate results are kept, so a change
+/// only recomputes the steps from the changed one on.
+#[derive(Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Pipeline {
+    steps: Vec<Step>,
+    /// the results of the first steps, the others have to be recomputed
+    #[serde(skip)]
+    results: Vec<PixGrid>,
+    /// generation and display settings of the source the results were computed from
+    #[serde(skip)]
+    source: Option<(u64, Display)>,
+    #[serde(skip)]
+    views: Vec<GridView>,
+    /// the step whose parameters are in the tool panel, with the tool and parameters
+    /// the panel had before, which come back when the editing ends
+    #[serde(skip)]
+    editing: Option<(usize, Tool, ToolVars)>,
+}
+
+impl Pipeline {
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// check loaded steps, see `ToolVars::is_consistent`. the pen can't be a step.
+    pub fn is_consistent(&self) -> bool {
+        self.steps.iter().all(|step| step.tool != Tool::Pen && step.vars.is_consistent())
+    }
+
+    /// forget the results from step i on
+    fn invalidate_from(&mut self, i: usize) {
+        self.results.truncate(i);
+    }
+
+    pub fn push(&mut self, tool: Tool, vars: &ToolVars) {
+        self.steps.push(Step { tool, vars: vars.clone(), enabled: true });
+    }
+
+    /// remove a step. the step must not be edited, see `stop_edit`.
+    pub fn remove(&mut self, i: usize) {
+        self.steps.remove(i);
+        self.invalidate_from(i);
+        if let Some((edited, ..)) = &mut self.editing {
+            if *edited > i {
+                *edited -= 1;
+            }
+        }
+    }
+
+    /// swap step i with the one after it
+    pub fn move_down(&mut self, i: usize) {
+        self.steps.swap(i, i + 1);
+        self.invalidate_from(i);
+        if let Some((edited, ..)) = &mut self.editing {
+            if *edited == i {
+                *edited = i + 1;
+            } else if *edited == i + 1 {
+                *edited = i;
+            }
+        }
+    }
+
+    pub fn set_enabled(&mut self, i: usize, enabled: bool) {
+        if self.steps[i].enabled != enabled {
+            self.steps[i].enabled = enabled;
+            self.invalidate_from(i);
+        }
+    }
+
+    pub fn editing(&self) -> Option<usize> {
+        self.editing.as_ref().map(|(i, ..)| *i)
+    }
+
+    /// show the parameters of step i in the tool panel, which are `tool` and `vars`
+    pub fn start_edit(&mut self, i: usize, tool: &mut Tool, vars: &mut ToolVars) {
+        self.stop_edit(tool, vars);
+        let step = &self.steps[i];
+        let before = (std::mem::replace(tool, step.tool), std::mem::replace(vars, step.vars.clone()));
+        self.editing = Some((i, before.0, before.1));
+    }
+
+    /// give the tool panel its own tool and parameters back
+    pub fn stop_edit(&mut self, tool: &mut Tool, vars: &mut ToolVars) {
+        if let Some((_, before_tool, before_vars)) = self.editing.take() {
+            *tool = before_tool;
+            *vars = before_vars;
+        }
+    }
+
+    /// take over the changes the user made in the tool panel to the edited step.
+    /// the pen can't be a step, choosing it keeps the step's tool.
+    /// the parameters are only compared by their revision, so this is cheap to call every frame.
+    pub fn sync(&mut self, tool: Tool, vars: &ToolVars) {
+        let Some((i, ..)) = self.editing else {
+            return;
+        };
+        let step = &mut self.steps[i];
+        let tool = if tool == Tool::Pen { step.tool } else { tool };
+        if step.tool != tool || step.vars.revision() != vars.revision() {
+            step.tool = tool;
+            step.vars = vars.clone();
+            self.invalidate_from(i);
+        }
+    }
+
+    /// compute the results that are missing, all of them if the source changed
+    pub fn update(&mut self, src: &PixGrid) {
+        let source = Some((src.generation(), src.display));
+        if self.source != source {
+            self.results.clear();
+            self.source = source;
+        }
+        while self.results.len() < self.steps.len() {
+            let i = self.results.len();
+            let mut input = self.results.last().unwrap_or(src).clone();
+            let mut output = input.clone();
+            let step = &self.steps[i];
+            if step.enabled {
+                step.tool.apply_to_whole_image(&step.vars, &mut input, &mut output);
+            }
+            self.results.push(output);
+        }
+    }
+
+    /// the result of the last step, as of the last `update`
+    pub fn result(&self) -> Option<&PixGrid> {
+        self.results.last()
+    }
+
+    /// draw the result of every step, labeled with its tool. `view` gives the cell size.
+    pub fn draw_results(&mut self, ui: &mut egui::Ui, view: &GridView) {
+        self.views.resize_with(self.results.len(), GridView::default);
+        ui.horizontal_wrapped(|ui| {
+            for (i, (result, result_view)) in self.results.iter().zip(&mut self.views).enumerate() {
+                let step = &self.steps[i];
+                ui.vertical(|ui| {
+                    let label = format!("{}. {}", i + 1, step.tool.name());
+                    // the result of a frequency step has the size of its input
+                    if step.uses_preset_mask(result.width(), result.height()) {
+                        ui.label(format!("{} (preset mask)", label))
+                            .on_hover_text("The mask was painted on another size, the preset filter is applied instead");
+                    } else {
+                        ui.add_enabled(step.enabled, egui::Label::new(label));
+                    }
+                    result_view.pix_size = (view.pix_size / 3).max(2);
+                    result_view.margin = view.margin.min(1);
+                    result_view.draw(ui, result);
+                });
+            }
+        });
+    }
+}
@@ -1,173 +1,442 @@
-use egui::{Sense, Rounding, Color32, Stroke, Pos2, Rect};
+use std::sync::atomic::{AtomicU64, Ordering};
 
+use egui::Color32;
+
+use crate::color::LumaWeights;
+use crate::image::ImageBuf;
+pub use crate::image::{Border, BorderMode, BorderTap};
+
+/// largest supported width and height of a grid
+pub const MAX_SIZE: u32 = 512;
+
+/// how the values of a grid, which may be negative or above 255, become gray levels
+#[derive(PartialEq, Clone, Copy, Default, serde::Deserialize, serde::Serialize)]
+pub enum DisplayMode {
+    /// cut off everything outside of 0..=255
+    #[default]
+    Clamp,
+    /// value * scale + offset, then clamped
+    OffsetScale,
+    /// the absolute value, clamped
+    Abs,
+    /// map the smallest value to 0 and the largest to 255
+    Normalize,
+}
+
+/// which channels of an rgb grid are shown
+#[derive(PartialEq, Clone, Copy, Default, serde::Deserialize, serde::Serialize)]
+pub enum ChannelView {
+    #[default]
+    All,
+    /// a single channel, as gray
+    Channel(usize),
+}
+
+#[derive(PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Display {
+    pub mode: DisplayMode,
+    pub offset: f32,
+    pub scale: f32,
+    pub view: ChannelView,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self { mode: DisplayMode::Clamp, offset: 127.0, scale: 1.0, view: ChannelView::All }
+    }
+}
+
+/// the display mapping of a grid, with the value range already looked up
+pub struct DisplayMap {
+    display: Display,
+    min: f32,
+    max: f32,
+    color: bool,
+}
+
+impl DisplayMap {
+    pub fn apply(&self, v: f32) -> u8 {
+        let v = match self.display.mode {
+            DisplayMode::Clamp => v,
+            DisplayMode::OffsetScale => v * self.display.scale + self.display.offset,
+            DisplayMode::Abs => v.abs(),
+            DisplayMode::Normalize => {
+                if self.max > self.min { (v - self.min) / (self.max - self.min) * 255.0 } else { 0.0 }
+            },
+        };
+        v.round().clamp(0.0, 255.0) as u8
+    }
+
+    /// the color shown for a pixel, see `PixGrid::pixel`
+    pub fn color(&self, px: [f32; 3]) -> Color32 {
+        match (self.color, self.display.view) {
+            (true, ChannelView::All) => Color32::from_rgb(self.apply(px[0]), self.apply(px[1]), self.apply(px[2])),
+            _ => Color32::from_gray(self.level(px)),
+        }
+    }
+
+    /// the gray level shown for a pixel. rgb pixels are shown by their luma.
+    pub fn level(&self, px: [f32; 3]) -> u8 {
+        match (self.color, self.display.view) {
+            (true, ChannelView::All) => {
+                let levels = px.map(|v| self.apply(v) as f32);
+                LumaWeights::Rec601.luma(levels).round() as u8
+            },
+            (true, ChannelView::Channel(c)) => self.apply(px[c.min(2)]),
+            (false, _) => self.apply(px[0]),
+        }
+    }
+}
+
+/// an image with one (gray) or three (rgb) channels. the values are floats, so results of
+/// arithmetic operations keep their sign and precision until they are quantized. operations
+/// that are defined on gray levels, like lookup tables and histograms, work on the displayed values.
+///
+/// the single value accessors read the first channel. the tools work on the channels
+/// one by one, see `plane`. the grid is shown by a `GridView`.
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
 pub struct PixGrid {
-    width: u32,
-    height: u32,
-    pixels: Vec<Vec<u8>>,
-
-    // ui stuff
-    pub pix_size: u32,
-    pub margin: u32,
-    hovered_idx: Option<(u32, u32)>,
-    pressed: bool,
-    rect: Rect,
+    /// one plane per channel, all of the same size
+    #[serde(default)]
+    channels: Vec<ImageBuf<f32>>,
+    /// the single plane of states from before there were channels, see `PixGrid::migrate`
+    #[serde(default, skip_serializing)]
+    pixels: ImageBuf<f32>,
+    #[serde(default)]
+    pub display: Display,
+    /// changes whenever the pixels change, so views know when to update
+    #[serde(skip, default = "next_generation")]
+    generation: u64,
+}
+
+/// source of the generations, shared by all grids so a copy with other pixels can't have the same one
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+fn next_generation() -> u64 {
+    NEXT_GENERATION.fetch_add(1, Ordering::Relaxed)
+}
+
+/// a value with at most the given number of decimals, without trailing zeros
+pub fn format_value(v: f32, decimals: usize) -> String {
+    let s = format!("{:.*}", decimals, v);
+    let s = if s.contains('.') { s.trim_end_matches('0').trim_end_matches('.') } else { &s };
+    // rounding small negative values gives "-0"
+    if s == "-0" { "0".to_string() } else { s.to_string() }
 }
 
 impl PixGrid {
-    pub fn new(width: u32, height: u32, init_color: u8, pix_size: u32) -> Self {
-        let mut s = Self {
-            width,
-            height,
-            pixels: Vec::with_capacity(height as usize),
-            pix_size,
-            margin: 3,
-            hovered_idx: None,
-            pressed: false,
-            rect: Rect::from_min_max(Pos2::new(0.0, 0.0), Pos2::new(0.0, 0.0)),
+    pub fn new(width: u32, height: u32, init_color: u8) -> Self {
+        Self::from_planes(vec![ImageBuf::new(width, height, init_color as f32)])
+    }
+
+    /// a grid with the given channels, see `set_planes`
+    pub fn from_planes(planes: Vec<ImageBuf<f32>>) -> Self {
+        let mut grid = Self {
+            channels: Vec::new(),
+            pixels: ImageBuf::default(),
+            display: Display::default(),
+            generation: 0,
         };
-        for _y in 0..height {
-            let mut row_vec = Vec::with_capacity(width as usize);
-            for _x in 0..width {
-                row_vec.push(init_color);
-            }
-            s.pixels.push(row_vec);
+        grid.set_planes(planes);
+        grid
+    }
+
+    /// move the pixels of a state from before there were channels into the first channel
+    pub fn migrate(&mut self) {
+        if self.channels.is_empty() {
+            self.channels = vec![std::mem::take(&mut self.pixels)];
+            self.touch();
         }
-        s
     }
 
+    /// note that the pixels changed
+    fn touch(&mut self) {
+        self.generation = next_generation();
+    }
+
+    /// changes whenever the pixels change. copies with the same pixels may have the same generation.
+    pub fn generation(&self) -> u64 { self.generation }
+
     /// get the value without any tests
-    pub fn get(&self, x: u32, y: u32) -> u8 {
-        self.pixels[y as usize][x as usize]
+    pub fn get(&self, x: u32, y: u32) -> f32 {
+        self.channels[0].get(x, y)
+    }
+
+    /// all the channels at a pixel. gray values are repeated in all three.
+    pub fn pixel(&self, x: u32, y: u32) -> [f32; 3] {
+        let c = self.channels.len() - 1;
+        [self.channels[0].get(x, y), self.channels[1.min(c)].get(x, y), self.channels[2.min(c)].get(x, y)]
     }
 
     /// get the value at the given position, clamped to (0, size).
     /// in other words, the image is extended at the edges.
-    pub fn get_clamped(&self, x: i32, y: i32) -> u8 {
-        let x = x.clamp(0, self.width as i32 - 1);
-        let y = y.clamp(0, self.height as i32 - 1);
-        self.pixels[y as usize][x as usize]
+    pub fn get_clamped(&self, x: i32, y: i32) -> f32 {
+        self.channels[0].get_clamped(x, y)
     }
 
-    pub fn get_o(&self, x: u32, y: u32) -> Option<u8> {
-        if x < self.width && y < self.height {
-            Some(self.pixels[y as usize][x as usize])
-        } else {
-            None
-        }
+    /// get the value at the given position, which may be outside of the image.
+    /// returns None if the border mode says this position must not be used.
+    pub fn get_border(&self, x: i32, y: i32, border: &Border) -> Option<f32> {
+        self.channels[0].get_border(x, y, border)
     }
 
-    /// if x,y is a valid index, set the color.
+    /// see `ImageBuf::resolve_border`
+    pub fn resolve_border(&self, x: i32, y: i32, border: &Border) -> BorderTap {
+        self.channels[0].resolve_border(x, y, border)
+    }
+
+    pub fn in_bounds(&self, x: i32, y: i32) -> bool {
+        self.channels[0].in_bounds(x, y)
+    }
+
+    /// if x,y is a valid index, set all the channels to the color.
     /// return if the color was set.
-    pub fn try_set(&mut self, x: i32, y: i32, color: u8) -> bool {
-        if x >= 0 && (x as u32) < self.width && y >= 0 && (y as u32) < self.height {
-            self.pixels[y as usize][x as usize] = color;
-            return true;
+    pub fn try_set(&mut self, x: i32, y: i32, color: f32) -> bool {
+        self.try_set_pixel(x, y, &[color; 3])
+    }
+
+    /// if x,y is a valid index, set the channels to the given values.
+    /// a single value is used for all the channels, extra values are ignored by gray grids.
+    /// without any values nothing is set.
+    /// the generation only changes if a value does, so holding the pen still costs nothing.
+    pub fn try_set_pixel(&mut self, x: i32, y: i32, values: &[f32]) -> bool {
+        if values.is_empty() || !self.in_bounds(x, y) {
+            return false;
         }
-        false
-    }
-
-    pub fn draw_outline(&self, ui: &mut egui::Ui, from_ix: u32, from_iy: u32, to_ix: u32, to_iy: u32) {
-        // instead of drawing invividual lines, draw a rect in the background...
-        let br_rect = Rect::from_min_max(
-            Pos2::new(
-                (from_ix * (self.pix_size + self.margin)) as f32 + self.rect.min.x,
-                (from_iy * (self.pix_size + self.margin)) as f32 + self.rect.min.y),
-            Pos2::new(
-                ((to_ix + 1) * self.pix_size + (to_ix + 2) * self.margin) as f32 + self.rect.min.x,
-                ((to_iy + 1) * self.pix_size + (to_iy + 2) * self.margin) as f32 + self.rect.min.y)
-        );
-        ui.painter().rect(br_rect, Rounding::ZERO, Color32::from_rgb(20, 200, 20), Stroke::NONE);
-
-        // then redraw the boxes on top
-        for iy in from_iy..=to_iy {
-            for ix in from_ix..=to_ix {
-                let color = self.get(ix, iy);
-                self.draw_rect_at_idx(ui, ix, iy, color);
+        let mut changed = false;
+        for (c, plane) in self.channels.iter_mut().enumerate() {
+            let v = values[c.min(values.len() - 1)];
+            if plane.get(x as u32, y as u32) != v {
+                plane.set(x as u32, y as u32, v);
+                changed = true;
             }
         }
+        if changed {
+            self.touch();
+        }
+        true
     }
 
-    pub fn draw_outline_clamped(&self, ui: &mut egui::Ui, from_ix: i32, from_iy: i32, to_ix: i32, to_iy: i32) {
-        //println!("before: {} {}, {} {} (as: {} {})", from_ix, from_iy, to_ix, to_iy, from_ix as u32, from_iy as u32);
-        let from_ix = from_ix.clamp(0, self.width as i32 - 1) as u32;
-        let from_iy = from_iy.clamp(0, self.height as i32 - 1) as u32;
-        let to_ix = to_ix.clamp(0, self.width as i32 - 1) as u32;
-        let to_iy = to_iy.clamp(0, self.height as i32 - 1) as u32;
-        //println!("after: {} {}, {} {}", from_ix, from_iy, to_ix, to_iy);
-        self.draw_outline(ui, from_ix, from_iy, to_ix, to_iy);
+    /// sets the whole image to color
+    pub fn reset_to_color(&mut self, color: u8) {
+        self.map_values(|_| color as f32);
     }
 
-    pub fn try_draw_rect_at_idx(&self, ui: &mut egui::Ui, ix: i32, iy: i32, color: u8) {
-        if ix >= 0 && (ix as u32) < self.width && iy >= 0 && (iy as u32) < self.height {
-            self.draw_rect_at_idx(ui, ix as u32, iy as u32, color);
+    /// sets the whole image to the color given per channel, see `try_set_pixel`
+    pub fn reset_to_pixel(&mut self, values: &[f32]) {
+        if values.is_empty() {
+            return;
+        }
+        for (c, plane) in self.channels.iter_mut().enumerate() {
+            let v = values[c.min(values.len() - 1)];
+            plane.iter_mut().for_each(|p| *p = v);
         }
+        self.touch();
     }
 
-    pub fn draw_rect_at_idx(&self, ui: &mut egui::Ui, ix: u32, iy: u32, color: u8) {
-        let x = ((self.margin + self.pix_size) * ix + self.margin) as f32 + self.rect.min.x;
-        let y = ((self.margin + self.pix_size) * iy + self.margin) as f32 + self.rect.min.y ;
-        ui.painter().rect(
-            Rect::from_min_max(Pos2::new(x, y), Pos2::new(x + self.pix_size as f32, y + self.pix_size as f32)),
-            Rounding::ZERO,
-            Color32::from_gray(color),
-            Stroke::NONE
-        );
-    }
-
-    // for now just draw, no interactivity
-    pub fn draw(&mut self, ui: &mut egui::Ui) {
-        // to draw n pixel boxes, we want n * pixel size and (n+1) * margin size
-        let desired_size = egui::vec2(
-            (self.width * (self.pix_size + self.margin) + self.margin) as f32,
-            (self.height * (self.pix_size + self.margin) + self.margin) as f32);
-        let (rect, response) = ui.allocate_exact_size(desired_size, Sense::click_and_drag());
-        self.rect = rect;
-
-        // check for hover and mouse press (button down, not just click so tools can be dragged)
-        if let Some(pos) = response.hover_pos() {
-            let pos = pos - self.rect.min.to_vec2();
-            // clamping in case the pointer is on the last margin
-            let ix = (pos.x as u32 / (self.pix_size + self.margin)).clamp(0, self.width - 1);
-            let iy = (pos.y as u32 / (self.pix_size + self.margin)).clamp(0, self.height - 1);
-            self.hovered_idx = Some((ix, iy));
-            self.pressed = response.is_pointer_button_down_on();
+    /// replace the image with a single, nonempty plane. the grid becomes gray.
+    pub fn set_image(&mut self, img: ImageBuf<f32>) {
+        self.set_planes(vec![img]);
+    }
+
+    /// replace the image with one or three nonempty planes of the same size
+    pub fn set_planes(&mut self, planes: Vec<ImageBuf<f32>>) {
+        // grids like the spectrum are set every frame, but mostly to the same values
+        if planes == self.channels {
+            return;
+        }
+        self.channels = planes;
+        assert!(self.is_consistent());
+        self.touch();
+    }
+
+    /// replace one channel with a plane of the same size
+    pub fn set_plane(&mut self, c: usize, plane: ImageBuf<f32>) {
+        assert!(plane.width() == self.width() && plane.height() == self.height());
+        self.channels[c] = plane;
+        self.touch();
+    }
+
+    pub fn plane(&self, c: usize) -> &ImageBuf<f32> {
+        &self.channels[c]
+    }
+
+    pub fn planes(&self) -> &[ImageBuf<f32>] {
+        &self.channels
+    }
+
+    /// change the size, keeping the content anchored at the top left corner.
+    /// new pixels get the color `fill`, or repeat the nearest edge pixel if it is None.
+    pub fn resize(&mut self, width: u32, height: u32, fill: Option<u8>) {
+        let planes = self.channels.iter().map(|plane| {
+            ImageBuf::from_fn(width, height, |x, y| match fill {
+                Some(color) if !plane.in_bounds(x as i32, y as i32) => color as f32,
+                _ => plane.get_clamped(x as i32, y as i32),
+            })
+        }).collect();
+        self.set_planes(planes);
+    }
+
+    pub fn copy_pixels_from(&mut self, other: &PixGrid) {
+        self.channels = other.channels.clone();
+        self.touch();
+    }
+
+    /// number of stored values, over all the channels
+    pub fn value_count(&self) -> usize {
+        self.channels.len() * (self.width() * self.height()) as usize
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn is_color(&self) -> bool {
+        self.channels.len() == 3
+    }
+
+    /// a gray grid with a copy of one channel, which has the same display mapping
+    pub fn channel(&self, c: usize) -> PixGrid {
+        let mut grid = PixGrid::from_planes(vec![self.channels[c].clone()]);
+        grid.display = Display { view: ChannelView::All, ..self.display };
+        grid
+    }
+
+    /// a gray grid for each channel
+    pub fn channel_grids(&self) -> Vec<PixGrid> {
+        (0..self.channel_count()).map(|c| self.channel(c)).collect()
+    }
+
+    /// turn a gray grid into an rgb one by repeating the values, or an rgb grid into a gray one
+    pub fn set_color(&mut self, color: bool, weights: LumaWeights) {
+        if color == self.is_color() {
+            return;
+        }
+        if color {
+            self.channels = vec![self.channels[0].clone(); 3];
         } else {
-            self.hovered_idx = None;
-            self.pressed = false;
+            let gray = ImageBuf::from_fn(self.width(), self.height(), |x, y| weights.luma(self.pixel(x, y)));
+            self.channels = vec![gray];
+            self.display.view = ChannelView::All;
         }
+        self.touch();
+    }
 
-        if ui.is_rect_visible(self.rect) {
-            // first, draw the background:
-            let painter = ui.painter();
-            painter.rect(self.rect, Rounding::ZERO, Color32::from_gray(20), Stroke::NONE);
+    /// a gray grid with the luma of an rgb grid, or a copy of a gray one
+    pub fn to_gray(&self, weights: LumaWeights) -> PixGrid {
+        let mut grid = self.clone();
+        grid.set_color(false, weights);
+        grid
+    }
 
-            // draw the pixel boxes. could also cache them.
-            for iy in 0..self.height {
-                for ix in 0..self.width {
-                    self.draw_rect_at_idx(ui, ix, iy, self.pixels[iy as usize][ix as usize]);
-                }
-            }
+    /// replace every value v with f(v)
+    pub fn map_values(&mut self, f: impl Fn(f32) -> f32) {
+        for v in self.channels.iter_mut().flat_map(|plane| plane.iter_mut()) {
+            *v = f(*v);
         }
+        self.touch();
     }
 
-    /// sets the whole image to color
-    pub fn reset_to_color(&mut self, color: u8) {
-        for iy in 0..self.height {
-            for ix in 0..self.width {
-                self.pixels[iy as usize][ix as usize] = color;
-            }
+    /// replace the channels of every pixel with f(channels). only for rgb grids.
+    pub fn map_pixels(&mut self, f: impl Fn([f32; 3]) -> [f32; 3]) {
+        let [r, g, b] = self.channels.as_mut_slice() else {
+            panic!("only rgb grids have pixels to map");
+        };
+        for ((r, g), b) in r.iter_mut().zip(g.iter_mut()).zip(b.iter_mut()) {
+            [*r, *g, *b] = f([*r, *g, *b]);
         }
+        self.touch();
+    }
+
+    /// smallest and largest value
+    pub fn value_range(&self) -> (f32, f32) {
+        self.channels.iter().flat_map(|plane| plane.iter())
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), v| (lo.min(v), hi.max(v)))
+    }
+
+    pub fn display_map(&self) -> DisplayMap {
+        let (min, max) = match self.display.mode {
+            DisplayMode::Normalize => self.value_range(),
+            _ => (0.0, 0.0),
+        };
+        DisplayMap { display: self.display, min, max, color: self.is_color() }
+    }
+
+    /// the display mapping of a single channel, as the tools see it when they process
+    /// the channels one by one
+    pub fn channel_map(&self, c: usize) -> DisplayMap {
+        let (min, max) = match self.display.mode {
+            DisplayMode::Normalize => self.channels[c].iter()
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), v| (lo.min(v), hi.max(v))),
+            _ => (0.0, 0.0),
+        };
+        let display = Display { view: ChannelView::All, ..self.display };
+        DisplayMap { display, min, max, color: false }
+    }
+
+    /// the gray level shown at a pixel. for the whole image, `display_map` is faster.
+    pub fn get_displayed(&self, x: u32, y: u32) -> u8 {
+        self.display_map().level(self.pixel(x, y))
     }
 
-    pub fn copy_pixels_from(&mut self, other: &mut PixGrid) {
-        self.width = other.width;
-        self.height = other.height;
-        self.pixels = other.pixels.clone();
+    /// replace the values with the displayed gray levels, so they become plain 8 bit values again
+    pub fn quantize(&mut self) {
+        let map = self.display_map();
+        self.map_values(|v| map.apply(v) as f32);
+        self.display.mode = DisplayMode::Clamp;
     }
 
-    pub fn pressed(&self) -> bool { self.pressed }
-    pub fn hovered_idx(&self) -> Option<(u32, u32)> { self.hovered_idx }
-    pub fn width(&self) -> u32 { self.width }
-    pub fn height(&self) -> u32 { self.height }
+    /// check that both grids have the same size and number of channels,
+    /// as the tools expect from the source and target grid
+    pub fn same_shape(&self, other: &PixGrid) -> bool {
+        self.width() == other.width() && self.height() == other.height() && self.channel_count() == other.channel_count()
+    }
+
+    /// compare only the image data, ignoring the display settings
+    pub fn same_pixels(&self, other: &PixGrid) -> bool {
+        self.channels == other.channels
+    }
+
+    /// check that there are one or three nonempty planes of the same size.
+    /// used to reject damaged persisted state, since all the accessors index without checks.
+    pub fn is_consistent(&self) -> bool {
+        (self.channels.len() == 1 || self.channels.len() == 3)
+            && !self.channels[0].is_empty()
+            && self.channels.iter().all(|plane| {
+                plane.width() == self.channels[0].width() && plane.height() == self.channels[0].height()
+            })
+    }
+
+    pub fn width(&self) -> u32 { self.channels[0].width() }
+    pub fn height(&self) -> u32 { self.channels[0].height() }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb() -> PixGrid {
+        PixGrid::from_planes((0..3).map(|c| ImageBuf::from_fn(2, 2, |x, y| (c * 10 + y * 2 + x) as f32)).collect())
+    }
+
+    #[test]
+    fn setting_no_values_does_nothing() {
+        let mut grid = rgb();
+        let generation = grid.generation();
+        assert!(!grid.try_set_pixel(0, 0, &[]));
+        grid.reset_to_pixel(&[]);
+        assert_eq!(grid.generation(), generation);
+        assert_eq!(grid.pixel(0, 0), [0.0, 10.0, 20.0]);
+    }
+
+    #[test]
+    fn map_pixels_changes_every_pixel() {
+        let mut grid = rgb();
+        let generation = grid.generation();
+        grid.map_pixels(|[r, g, b]| [b, r, g]);
+        assert_ne!(grid.generation(), generation);
+        assert_eq!(grid.pixel(0, 0), [20.0, 0.0, 10.0]);
+        assert_eq!(grid.pixel(1, 1), [23.0, 3.0, 13.0]);
+    }
+}
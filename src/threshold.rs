@@ -0,0 +1,199 @@
+use crate::image::{Border, ImageBuf};
+use crate::pix_grid::{Display, DisplayMap, PixGrid};
+
+/// how far the window of adaptive thresholding may reach from its center
+pub const MAX_ADAPTIVE_RADIUS: i32 = 7;
+
+#[derive(PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum ThresholdMode {
+    /// the same, manually chosen threshold everywhere
+    Global,
+    /// the same threshold everywhere, chosen to best separate the two classes of pixels
+    Otsu,
+    /// compare each pixel with the mean of its window
+    AdaptiveMean,
+    /// compare each pixel with the gaussian weighted mean of its window
+    AdaptiveGaussian,
+}
+
+#[derive(PartialEq, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Thresholding {
+    pub mode: ThresholdMode,
+    /// threshold for `ThresholdMode::Global`
+    pub value: u8,
+    /// window radius for the adaptive modes
+    pub radius: i32,
+    /// subtracted from the local mean in the adaptive modes
+    pub offset: f32,
+}
+
+impl Default for Thresholding {
+    fn default() -> Self {
+        Self { mode: ThresholdMode::Global, value: 127, radius: 2, offset: 5.0 }
+    }
+}
+
+/// otsu's threshold: the value that maximizes the variance between the pixels
+/// at or below it and the pixels above it. like the histogram, it uses the displayed values.
+pub fn otsu(img: &ImageBuf<f32>, map: &DisplayMap) -> u8 {
+    let mut hist = [0u32; 256];
+    for v in img.iter() {
+        hist[map.apply(v) as usize] += 1;
+    }
+    let total = (img.width() * img.height()) as f64;
+    let sum_all: f64 = hist.iter().enumerate().map(|(v, &n)| v as f64 * n as f64).sum();
+
+    let mut best = (0, -1.0);
+    let mut count_low = 0.0;
+    let mut sum_low = 0.0;
+    for (t, &n) in hist.iter().enumerate() {
+        count_low += n as f64;
+        sum_low += t as f64 * n as f64;
+        let count_high = total - count_low;
+        if count_low == 0.0 || count_high == 0.0 {
+            continue;
+        }
+        let mean_low = sum_low / count_low;
+        let mean_high = (sum_all - sum_low) / count_high;
+        let between_var = count_low * count_high * (mean_low - mean_high).powi(2);
+        if between_var > best.1 {
+            best = (t, between_var);
+        }
+    }
+    best.0 as u8
+}
+
+/// otsu's thresholds of the channels of a grid, only recomputed when its pixels or display settings change
+#[derive(Default)]
+pub struct OtsuCache {
+    /// generation and display settings of the grid the thresholds are for
+    source: Option<(u64, Display)>,
+    thresholds: Vec<u8>,
+}
+
+impl OtsuCache {
+    pub fn update(&mut self, grid: &PixGrid) {
+        let source = Some((grid.generation(), grid.display));
+        if self.source != source {
+            self.thresholds = (0..grid.channel_count()).map(|c| otsu(grid.plane(c), &grid.channel_map(c))).collect();
+            self.source = source;
+        }
+    }
+
+    /// one threshold per channel, as of the last `update`
+    pub fn thresholds(&self) -> &[u8] {
+        &self.thresholds
+    }
+}
+
+impl Thresholding {
+    /// the threshold at x, y. `otsu_value` is only used in otsu mode, as it depends on the whole image.
+    /// None if the window is skipped because of the border mode.
+    fn threshold_at(&self, x: i32, y: i32, src: &ImageBuf<f32>, border: &Border, otsu_value: u8) -> Option<f32> {
+        let r = self.radius;
+        // sigma as opencv derives it from the window size
+        let sigma = 0.3 * (r as f32 - 1.0) + 0.8;
+        let weight = |dx: i32, dy: i32| match self.mode {
+            ThresholdMode::AdaptiveGaussian => (-((dx * dx + dy * dy) as f32) / (2.0 * sigma * sigma)).exp(),
+            _ => 1.0,
+        };
+        match self.mode {
+            ThresholdMode::Global => Some(self.value as f32),
+            ThresholdMode::Otsu => Some(otsu_value as f32),
+            ThresholdMode::AdaptiveMean | ThresholdMode::AdaptiveGaussian => {
+                let mut sum = 0.0;
+                let mut weights = 0.0;
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        let w = weight(dx, dy);
+                        sum += w * src.get_border(x + dx, y + dy, border)?;
+                        weights += w;
+                    }
+                }
+                Some(sum / weights - self.offset)
+            },
+        }
+    }
+
+    /// binarize the pixel at x, y. `map` is the one `otsu_value` was computed with,
+    /// since otsu's threshold is a displayed level, the pixel is compared as it is shown.
+    pub fn apply_at(&self, x: i32, y: i32, src: &ImageBuf<f32>, border: &Border, map: &DisplayMap, otsu_value: u8) -> Option<f32> {
+        let t = self.threshold_at(x, y, src, border, otsu_value)?;
+        let v = src.get(x as u32, y as u32);
+        let v = if self.mode == ThresholdMode::Otsu { map.apply(v) as f32 } else { v };
+        Some(if v > t { 255.0 } else { 0.0 })
+    }
+
+    /// offsets of the pixels the result depends on
+    pub fn window(&self) -> Vec<(i32, i32)> {
+        match self.mode {
+            ThresholdMode::Global | ThresholdMode::Otsu => vec![(0, 0)],
+            _ => (-self.radius..=self.radius)
+                .flat_map(|y| (-self.radius..=self.radius).map(move |x| (x, y)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::BorderMode;
+    use crate::pix_grid::DisplayMode;
+
+    fn row(values: &[f32]) -> ImageBuf<f32> {
+        ImageBuf::from_rows(vec![values.to_vec()]).unwrap()
+    }
+
+    fn adaptive(radius: i32, offset: f32) -> Thresholding {
+        Thresholding { mode: ThresholdMode::AdaptiveMean, radius, offset, ..Thresholding::default() }
+    }
+
+    fn apply_row(threshold: &Thresholding, img: &ImageBuf<f32>, border: &Border) -> Vec<Option<f32>> {
+        let map = PixGrid::from_planes(vec![img.clone()]).display_map();
+        (0..img.width() as i32).map(|x| threshold.apply_at(x, 0, img, border, &map, 0)).collect()
+    }
+
+    #[test]
+    fn otsu_separates_two_modes() {
+        let img = row(&[40.0, 50.0, 60.0, 50.0, 190.0, 200.0, 210.0, 200.0, 200.0]);
+        let map = PixGrid::from_planes(vec![img.clone()]).display_map();
+        // every level between the modes separates them equally well, the first one wins
+        assert_eq!(otsu(&img, &map), 60);
+    }
+
+    #[test]
+    fn otsu_uses_the_displayed_levels() {
+        let img = row(&[0.1, 0.1, 0.9, 0.9]);
+        let mut grid = PixGrid::from_planes(vec![img.clone()]);
+        grid.display = Display { mode: DisplayMode::OffsetScale, scale: 255.0, offset: 0.0, ..Display::default() };
+        let map = grid.display_map();
+        let t = otsu(&img, &map);
+        assert_eq!(t, 26);
+        let otsu_mode = Thresholding { mode: ThresholdMode::Otsu, ..Thresholding::default() };
+        let border = Border::default();
+        assert_eq!(otsu_mode.apply_at(0, 0, &img, &border, &map, t), Some(0.0));
+        assert_eq!(otsu_mode.apply_at(2, 0, &img, &border, &map, t), Some(255.0));
+    }
+
+    #[test]
+    fn adaptive_mean_on_a_ramp() {
+        let img = row(&[0.0, 10.0, 20.0, 30.0, 40.0]);
+        let border = Border::default();
+        // inside the ramp, every pixel is the mean of its window, only the clamped end is above it
+        assert_eq!(apply_row(&adaptive(1, 0.0), &img, &border),
+            vec![Some(0.0), Some(0.0), Some(0.0), Some(0.0), Some(255.0)]);
+        assert_eq!(apply_row(&adaptive(1, 5.0), &img, &border), vec![Some(255.0); 5]);
+        let skip = Border { mode: BorderMode::Skip, constant: 0 };
+        assert_eq!(apply_row(&adaptive(1, 0.0), &img, &skip), vec![None; 5]);
+    }
+
+    #[test]
+    fn adaptive_mean_on_a_step() {
+        let img = row(&[0.0, 0.0, 0.0, 100.0, 100.0, 100.0]);
+        let border = Border::default();
+        assert_eq!(apply_row(&adaptive(1, 0.0), &img, &border),
+            vec![Some(0.0), Some(0.0), Some(0.0), Some(255.0), Some(0.0), Some(0.0)]);
+    }
+}